@@ -0,0 +1,29 @@
+#ifdef GL_ES
+precision mediump float;
+#endif
+
+uniform sampler2D al_tex;
+varying vec4 varying_color;
+varying vec2 varying_texcoord;
+
+void main()
+{
+	vec3 color = texture2D(al_tex, varying_texcoord).rgb;
+
+	// Simulate deuteranopia in LMS space.
+	mat3 rgb2lms = mat3(
+		17.8824, 3.45565, 0.0299566,
+		43.5161, 27.1554, 0.184309,
+		4.11935, 3.86714, 1.46709);
+	mat3 lms2rgb = mat3(
+		0.0809444479, -0.0102485335, -0.000365296938,
+		-0.130504409, 0.0540193266, -0.00412161469,
+		0.116721066, -0.113614708, 0.693511405);
+	vec3 lms = rgb2lms * color;
+	vec3 simulated = lms2rgb * vec3(lms.x, 0.494207 * lms.x + 1.24827 * lms.z, lms.z);
+
+	// Move the lost red/green contrast into channels that are still visible.
+	vec3 error = color - simulated;
+	vec3 shift = vec3(0.0, 0.7 * error.r + error.g, 0.7 * error.r + error.b);
+	gl_FragColor = vec4(clamp(color + shift, 0.0, 1.0), 1.0) * varying_color;
+}
@@ -0,0 +1,27 @@
+#ifdef GL_ES
+precision mediump float;
+#endif
+
+uniform sampler2D al_tex;
+uniform vec2 buffer_size;
+varying vec4 varying_color;
+varying vec2 varying_texcoord;
+
+void main()
+{
+	// Barrel distortion.
+	vec2 uv = varying_texcoord * 2.0 - 1.0;
+	uv *= 1.0 + 0.08 * uv.yx * uv.yx;
+	if (abs(uv.x) > 1.0 || abs(uv.y) > 1.0)
+	{
+		gl_FragColor = vec4(0.0, 0.0, 0.0, 1.0);
+		return;
+	}
+	uv = uv * 0.5 + 0.5;
+
+	vec3 color = texture2D(al_tex, uv).rgb;
+	float scanline = 0.75 + 0.25 * sin(uv.y * buffer_size.y * 6.2831853);
+	vec2 edge = uv * (1.0 - uv.yx);
+	float vignette = clamp(pow(edge.x * edge.y * 15.0, 0.25), 0.0, 1.0);
+	gl_FragColor = vec4(color * scanline * vignette, 1.0) * varying_color;
+}
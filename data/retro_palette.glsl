@@ -0,0 +1,25 @@
+#ifdef GL_ES
+precision mediump float;
+#endif
+
+uniform sampler2D al_tex;
+uniform vec3 palette[16];
+varying vec4 varying_color;
+varying vec2 varying_texcoord;
+
+void main()
+{
+	vec3 color = texture2D(al_tex, varying_texcoord).rgb;
+	vec3 best = palette[0];
+	float best_dist = distance(color, best);
+	for (int i = 1; i < 16; i++)
+	{
+		float dist = distance(color, palette[i]);
+		if (dist < best_dist)
+		{
+			best = palette[i];
+			best_dist = dist;
+		}
+	}
+	gl_FragColor = vec4(best, 1.0) * varying_color;
+}
@@ -0,0 +1,91 @@
+use crate::error::Result;
+use crate::utils;
+use allegro::*;
+use std::path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn capture_dir(core: &Core) -> Result<path::PathBuf>
+{
+	let mut path_buf = path::PathBuf::new();
+	if cfg!(feature = "use_user_settings")
+	{
+		path_buf.push(
+			core.get_standard_path(StandardPath::UserData)
+				.map_err(|_| "Couldn't get standard path".to_string())?,
+		);
+	}
+	path_buf.push("screenshots");
+	Ok(path_buf)
+}
+
+fn timestamp() -> String
+{
+	let secs = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0);
+	utils::format_timestamp(secs)
+}
+
+pub fn save_screenshot(core: &Core, buffer: &Bitmap) -> Result<()>
+{
+	let mut path_buf = capture_dir(core)?;
+	std::fs::create_dir_all(&path_buf).map_err(|_| "Couldn't create directory".to_string())?;
+	let name = timestamp();
+	path_buf.push(format!("{}.png", name));
+	// Don't overwrite screenshots taken within the same second.
+	let mut idx = 1;
+	while path_buf.exists()
+	{
+		path_buf.set_file_name(format!("{}_{}.png", name, idx));
+		idx += 1;
+	}
+	utils::save_bitmap(buffer, path_buf.to_str().unwrap())
+}
+
+pub struct FrameDumper
+{
+	dir: Option<path::PathBuf>,
+	frame: i64,
+	saved: i64,
+}
+
+impl FrameDumper
+{
+	pub fn new() -> Self
+	{
+		Self {
+			dir: None,
+			frame: 0,
+			saved: 0,
+		}
+	}
+
+	pub fn dump_frame(&mut self, core: &Core, buffer: &Bitmap, interval: i32) -> Result<()>
+	{
+		if interval <= 0
+		{
+			return Ok(());
+		}
+		self.frame += 1;
+		if (self.frame - 1) % interval as i64 != 0
+		{
+			return Ok(());
+		}
+		if self.dir.is_none()
+		{
+			let mut path_buf = capture_dir(core)?;
+			path_buf.push(format!("frames_{}", timestamp()));
+			std::fs::create_dir_all(&path_buf)
+				.map_err(|_| "Couldn't create directory".to_string())?;
+			self.dir = Some(path_buf);
+		}
+		let path_buf = self
+			.dir
+			.as_ref()
+			.unwrap()
+			.join(format!("frame_{:06}.png", self.saved));
+		self.saved += 1;
+		utils::save_bitmap(buffer, path_buf.to_str().unwrap())
+	}
+}
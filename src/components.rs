@@ -1,6 +1,7 @@
 use crate::{game_state, utils};
 use na::{Point2, Point3, Vector3};
 use nalgebra as na;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -34,26 +35,213 @@ pub struct GasCloud
 	pub growth_rate: f32,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct Freezable
+// Chill lost per second.
+const THAW_RATE: f32 = 0.25;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StatusEffectType
 {
-	pub amount: f32,
+	Burning,
+	Frozen,
+	Slowed,
+	Stunned,
+	Poisoned,
 }
 
-impl Freezable
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stacking
 {
-	pub fn is_frozen(&self) -> bool
+	// Keep the higher strength and the later end time.
+	Refresh,
+	// The new effect overwrites the old one.
+	Replace,
+	// Strengths add up to the cap, the end time is refreshed.
+	Intensify(f32),
+}
+
+impl StatusEffectType
+{
+	// Being immune to this damage type also makes you immune to the effect.
+	pub fn damage_type(&self) -> Option<DamageType>
+	{
+		match self
+		{
+			StatusEffectType::Burning => Some(DamageType::Flame),
+			StatusEffectType::Frozen | StatusEffectType::Slowed => Some(DamageType::Cold(0.)),
+			StatusEffectType::Poisoned => Some(DamageType::Poison),
+			StatusEffectType::Stunned => None,
+		}
+	}
+
+	pub fn stacking(&self) -> Stacking
+	{
+		match self
+		{
+			StatusEffectType::Burning | StatusEffectType::Slowed | StatusEffectType::Stunned =>
+			{
+				Stacking::Refresh
+			}
+			// The duration comes from the chill, which already accounts for prior freezing.
+			StatusEffectType::Frozen => Stacking::Replace,
+			StatusEffectType::Poisoned => Stacking::Intensify(10.),
+		}
+	}
+
+	pub fn tint(&self) -> (f32, f32, f32)
 	{
-		self.amount > 1.
+		match self
+		{
+			StatusEffectType::Burning => (1., 0.6, 0.4),
+			StatusEffectType::Frozen => (0.5, 0.5, 1.),
+			StatusEffectType::Slowed => (0.8, 0.8, 1.),
+			StatusEffectType::Stunned => (1., 1., 0.6),
+			StatusEffectType::Poisoned => (0.6, 1., 0.6),
+		}
+	}
+
+	pub fn label(&self) -> &'static str
+	{
+		match self
+		{
+			StatusEffectType::Burning => "FIRE",
+			StatusEffectType::Frozen => "ICE",
+			StatusEffectType::Slowed => "SLOW",
+			StatusEffectType::Stunned => "STUN",
+			StatusEffectType::Poisoned => "POISON",
+		}
 	}
 }
 
 #[derive(Debug, Copy, Clone)]
+pub struct StatusEffect
+{
+	pub effect_type: StatusEffectType,
+	// Damage per second for Burning and Poisoned, speed reduction for Slowed.
+	pub strength: f32,
+	pub until: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusEffects
+{
+	pub effects: Vec<StatusEffect>,
+	// Cold damage builds this up, above 1 you're frozen.
+	pub chill: f32,
+}
+
+impl StatusEffects
+{
+	pub fn new() -> Self
+	{
+		Self {
+			effects: vec![],
+			chill: 0.,
+		}
+	}
+
+	pub fn add(&mut self, effect: StatusEffect)
+	{
+		let existing = self
+			.effects
+			.iter_mut()
+			.find(|e| e.effect_type == effect.effect_type);
+		if let Some(existing) = existing
+		{
+			match effect.effect_type.stacking()
+			{
+				Stacking::Refresh =>
+				{
+					existing.strength = utils::max(existing.strength, effect.strength);
+					existing.until = utils::max(existing.until, effect.until);
+				}
+				Stacking::Replace => *existing = effect,
+				Stacking::Intensify(max_strength) =>
+				{
+					existing.strength =
+						utils::min(max_strength, existing.strength + effect.strength);
+					existing.until = utils::max(existing.until, effect.until);
+				}
+			}
+		}
+		else
+		{
+			self.effects.push(effect);
+		}
+	}
+
+	pub fn add_chill(&mut self, amount: f32, time: f64)
+	{
+		self.chill = utils::min(2., self.chill + amount);
+		if self.chill > 1.
+		{
+			// Chill thaws at a constant rate, so this is when it drops back to 1.
+			self.add(StatusEffect {
+				effect_type: StatusEffectType::Frozen,
+				strength: 1.,
+				until: time + ((self.chill - 1.) / THAW_RATE) as f64,
+			});
+		}
+		else
+		{
+			self.add(StatusEffect {
+				effect_type: StatusEffectType::Slowed,
+				strength: 0.5 * self.chill,
+				until: time + 1.,
+			});
+		}
+	}
+
+	pub fn update(&mut self, time: f64)
+	{
+		self.chill = utils::max(0., self.chill - THAW_RATE * utils::DT);
+		self.effects.retain(|e| e.until > time);
+	}
+
+	pub fn get(&self, effect_type: StatusEffectType) -> Option<&StatusEffect>
+	{
+		self.effects.iter().find(|e| e.effect_type == effect_type)
+	}
+
+	pub fn has(&self, effect_type: StatusEffectType) -> bool
+	{
+		self.get(effect_type).is_some()
+	}
+
+	// No moving, turning or shooting.
+	pub fn is_incapacitated(&self) -> bool
+	{
+		self.has(StatusEffectType::Frozen) || self.has(StatusEffectType::Stunned)
+	}
+
+	pub fn speed_factor(&self) -> f32
+	{
+		self.get(StatusEffectType::Slowed)
+			.map(|e| 1. - utils::clamp(e.strength, 0., 1.))
+			.unwrap_or(1.)
+	}
+
+	pub fn tint(&self) -> Option<(f32, f32, f32)>
+	{
+		[
+			StatusEffectType::Frozen,
+			StatusEffectType::Stunned,
+			StatusEffectType::Burning,
+			StatusEffectType::Poisoned,
+			StatusEffectType::Slowed,
+		]
+		.iter()
+		.find(|t| self.has(**t))
+		.map(|t| t.tint())
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 pub enum DamageType
 {
 	Regular,
 	Flame,
 	Cold(f32),
+	Poison,
 }
 
 impl DamageType
@@ -77,6 +265,11 @@ impl DamageType
 				DamageType::Cold(_) => true,
 				_ => false,
 			},
+			DamageType::Poison => match other
+			{
+				DamageType::Poison => true,
+				_ => false,
+			},
 		}
 	}
 }
@@ -86,6 +279,16 @@ pub struct Damage
 {
 	pub amount: f32,
 	pub damage_type: DamageType,
+	// Status effect the hit inflicts, if any.
+	pub effect: Option<HitEffect>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HitEffect
+{
+	pub effect_type: StatusEffectType,
+	pub strength: f32,
+	pub duration: f64,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -157,7 +360,7 @@ pub struct AmmoRegen
 	pub time_to_regen: f64,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum WeaponType
 {
 	SantaGun,
@@ -170,6 +373,7 @@ pub enum WeaponType
 	SnowmanGun,
 	BigSnowmanGun,
 	KrampusGun,
+	ElfGun,
 }
 
 impl WeaponType
@@ -188,6 +392,23 @@ impl WeaponType
 			WeaponType::SnowmanGun => 12.,
 			WeaponType::BigSnowmanGun => 14.,
 			WeaponType::KrampusGun => 14.,
+			WeaponType::ElfGun => 4.,
+		}
+	}
+
+	pub fn fire_sound(&self) -> &'static str
+	{
+		match self
+		{
+			WeaponType::RocketGun | WeaponType::KrampusGun => "data/rocket_fire.ogg",
+			WeaponType::FlameGun | WeaponType::BigFlameGun => "data/fire.ogg",
+			WeaponType::OrbGun => "data/orb_fire.ogg",
+			WeaponType::SantaGun | WeaponType::BuggyGun | WeaponType::ElfGun =>
+			{
+				"data/bullet_fire.ogg"
+			}
+			WeaponType::SnowmanGun | WeaponType::BigSnowmanGun => "data/snowball_fire.ogg",
+			WeaponType::FreezeGun => "data/freeze.ogg",
 		}
 	}
 
@@ -205,14 +426,16 @@ impl WeaponType
 			WeaponType::SnowmanGun => 2,
 			WeaponType::BigSnowmanGun => 1,
 			WeaponType::KrampusGun => 1,
+			WeaponType::ElfGun => 1,
 		}
 	}
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Weapon
 {
 	pub delay: f64,
+	#[serde(default)]
 	pub time_to_fire: f64,
 	pub weapon_type: WeaponType,
 	pub ammo: i32,
@@ -367,7 +590,7 @@ impl Weapon
 	}
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WeaponSet
 {
 	pub weapons: HashMap<WeaponType, Weapon>,
@@ -450,6 +673,13 @@ pub struct Sound
 	pub volume: f32,
 }
 
+pub struct AmbientSound
+{
+	pub sound: String,
+	pub volume: f32,
+	pub radius: f32,
+}
+
 pub struct Active
 {
 	pub active: bool,
@@ -474,18 +704,202 @@ pub struct Spawner
 	>,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TargetMode
+{
+	Toggle,
+	Activate,
+	Deactivate,
+	// These only affect counters.
+	Increment,
+	Decrement,
+	Reset,
+}
+
+impl TargetMode
+{
+	pub fn from_str(s: &str) -> Option<Self>
+	{
+		match s
+		{
+			"toggle" => Some(TargetMode::Toggle),
+			"activate" => Some(TargetMode::Activate),
+			"deactivate" => Some(TargetMode::Deactivate),
+			"increment" => Some(TargetMode::Increment),
+			"decrement" => Some(TargetMode::Decrement),
+			"reset" => Some(TargetMode::Reset),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct Target
+{
+	pub name: String,
+	pub mode: TargetMode,
+}
+
+#[derive(Clone, Debug)]
+pub enum TriggerShape
+{
+	Rect
+	{
+		start: Point2<f32>,
+		end: Point2<f32>,
+	},
+	Ellipse
+	{
+		start: Point2<f32>,
+		end: Point2<f32>,
+	},
+	Polygon(Vec<Point2<f32>>),
+}
+
+impl TriggerShape
+{
+	pub fn bounds(&self) -> (Point2<f32>, Point2<f32>)
+	{
+		match self
+		{
+			TriggerShape::Rect { start, end } | TriggerShape::Ellipse { start, end } =>
+			{
+				(*start, *end)
+			}
+			TriggerShape::Polygon(points) =>
+			{
+				let mut start = points[0];
+				let mut end = points[0];
+				for p in points
+				{
+					start = Point2::new(start.x.min(p.x), start.y.min(p.y));
+					end = Point2::new(end.x.max(p.x), end.y.max(p.y));
+				}
+				(start, end)
+			}
+		}
+	}
+
+	pub fn contains(&self, pos: Point2<f32>) -> bool
+	{
+		match self
+		{
+			TriggerShape::Rect { start, end } =>
+			{
+				pos.x >= start.x && pos.x <= end.x && pos.y >= start.y && pos.y <= end.y
+			}
+			TriggerShape::Ellipse { start, end } =>
+			{
+				let center = start + (end - start) / 2.;
+				let radii = (end - start) / 2.;
+				let d = pos - center;
+				(d.x / radii.x).powi(2) + (d.y / radii.y).powi(2) <= 1.
+			}
+			TriggerShape::Polygon(points) => utils::is_inside_simple_poly(points, pos),
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TriggerFilter
+{
+	Player,
+	Monster,
+	Neutral,
+	Vehicle,
+	Any,
+}
+
+impl TriggerFilter
+{
+	pub fn from_str(s: &str) -> Option<Self>
+	{
+		match s
+		{
+			"player" => Some(TriggerFilter::Player),
+			"monster" => Some(TriggerFilter::Monster),
+			"neutral" => Some(TriggerFilter::Neutral),
+			"vehicle" => Some(TriggerFilter::Vehicle),
+			"any" => Some(TriggerFilter::Any),
+			_ => None,
+		}
+	}
+}
+
 pub struct AreaTrigger
 {
-	pub start: Point2<f32>,
-	pub end: Point2<f32>,
-	pub targets: Vec<String>,
+	pub shape: TriggerShape,
+	pub filter: TriggerFilter,
+	pub targets: Vec<Target>,
+	pub exit_targets: Vec<Target>,
+	// Delay before it can fire again, None if it only fires once.
+	pub rearm_delay: Option<f64>,
+	pub time_to_rearm: f64,
+	pub occupied: bool,
 }
 
 pub struct Counter
 {
 	pub count: i32,
 	pub max_count: i32,
-	pub targets: Vec<String>,
+	pub targets: Vec<Target>,
+	// Starts counting again after firing, instead of removing itself.
+	pub repeat: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Gate
+{
+	And,
+	Or,
+	Not,
+}
+
+pub struct Relay
+{
+	pub gate: Gate,
+	// Names of the entities whose Active state is read, missing ones count as inactive.
+	pub inputs: Vec<String>,
+	pub targets: Vec<Target>,
+	pub output: bool,
+	pub once: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DialoguePage
+{
+	#[serde(default)]
+	pub speaker: String,
+	#[serde(default)]
+	pub portrait: String,
+	pub lines: Vec<String>,
+	#[serde(default = "default_page_duration")]
+	pub duration: f64,
+}
+
+fn default_page_duration() -> f64
+{
+	3.
+}
+
+pub struct Cutscene
+{
+	pub path: Vec<Point3<f32>>,
+	pub look_at: String,
+	pub duration: f64,
+	pub pages: Vec<DialoguePage>,
+	pub targets: Vec<Target>,
+}
+
+pub struct Objective
+{
+	pub text: String,
+	// Counter showing the progress, or a flag that completes the objective when active.
+	pub counter: String,
+	pub optional: bool,
+	pub progress: Option<(i32, i32)>,
+	pub complete: bool,
+	pub targets: Vec<Target>,
 }
 
 pub struct NextLevel
@@ -493,16 +907,26 @@ pub struct NextLevel
 	pub next_level: String,
 }
 
+pub struct Music
+{
+	pub playlist: Vec<String>,
+}
+
 pub struct Message
 {
 	pub message: String,
+	pub speaker: String,
+	pub color: allegro::Color,
+	pub duration: f64,
 }
 
 pub struct Trigger
 {
 	pub delay: f64,
 	pub time_to_trigger: f64,
-	pub targets: Vec<String>,
+	pub targets: Vec<Target>,
+	// Times left to fire, -1 to repeat forever.
+	pub repeat: i32,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -513,6 +937,143 @@ pub struct Moveable
 	pub can_strafe: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct VehiclePhysics
+{
+	// Under throttle, and when the throttle opposes the motion.
+	pub acceleration: f32,
+	pub braking: f32,
+	// Radians per second, reached at `full_turn_speed`.
+	pub turn_rate: f32,
+	pub full_turn_speed: f32,
+	// How quickly sideways velocity is cancelled, per second.
+	pub grip: f32,
+	pub ice_grip: f32,
+	// How quickly the vehicle slows down when coasting, per second.
+	pub drag: f32,
+	// Damage per unit of impact speed, for impacts faster than `min_ram_speed`.
+	pub ram_damage: f32,
+	pub min_ram_speed: f32,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DriveInput
+{
+	pub throttle: f32,
+	pub steer: f32,
+	pub on_ice: bool,
+}
+
+impl VehiclePhysics
+{
+	pub fn drive(&self, vel: &mut Velocity, dir: f32, input: DriveInput, max_speed: f32, dt: f32)
+	{
+		let forward = utils::dir_vec3(dir);
+		let mut forward_speed = vel.vel.dot(&forward);
+		let mut side_vel = vel.vel - forward * forward_speed;
+
+		if input.throttle != 0.
+		{
+			let accel = if input.throttle * forward_speed < 0.
+			{
+				self.braking
+			}
+			else
+			{
+				self.acceleration
+			};
+			forward_speed += input.throttle * accel * dt;
+		}
+		else
+		{
+			forward_speed *= (-self.drag * dt).exp();
+		}
+		forward_speed = utils::clamp(forward_speed, -max_speed / 2., max_speed);
+
+		// Turning leaves the old velocity partly sideways, so low grip makes it drift.
+		let grip = if input.on_ice
+		{
+			self.ice_grip
+		}
+		else
+		{
+			self.grip
+		};
+		side_vel *= (-grip * dt).exp();
+
+		vel.vel = forward * forward_speed + side_vel;
+		// Can't turn in place, and steering flips when reversing.
+		let turn_factor = utils::clamp(forward_speed / self.full_turn_speed, -1., 1.);
+		vel.dir_vel = input.steer * self.turn_rate * turn_factor;
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub enum AbilityType
+{
+	// Dashes forward, damaging whatever is in the way.
+	Charge
+	{
+		speed: f32,
+		duration: f64,
+		damage_rate: f32,
+	},
+	// Throws a present that explodes after a delay.
+	PresentBomb
+	{
+		fuse: f64, damage: f32, radius: f32
+	},
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ability
+{
+	pub ability_type: AbilityType,
+	pub cooldown: f64,
+	pub energy_cost: f32,
+	pub max_energy: f32,
+	// Per second.
+	pub energy_regen: f32,
+	#[serde(default)]
+	pub energy: f32,
+	#[serde(default)]
+	pub time_to_ready: f64,
+	#[serde(default)]
+	pub active: bool,
+	#[serde(default)]
+	pub active_until: f64,
+}
+
+impl Ability
+{
+	pub fn ready(&self, time: f64) -> bool
+	{
+		!self.active && time >= self.time_to_ready && self.energy >= self.energy_cost
+	}
+
+	// 1 when ready, limited by whichever of the cooldown and the energy is further off.
+	pub fn readiness(&self, time: f64) -> f32
+	{
+		let cooldown = if self.cooldown > 0.
+		{
+			1. - ((self.time_to_ready - time) / self.cooldown) as f32
+		}
+		else
+		{
+			1.
+		};
+		let energy = if self.energy_cost > 0.
+		{
+			self.energy / self.energy_cost
+		}
+		else
+		{
+			1.
+		};
+		utils::clamp(utils::min(cooldown, energy), 0., 1.)
+	}
+}
+
 pub struct Deleter
 {
 	pub targets: Vec<String>,
@@ -520,7 +1081,7 @@ pub struct Deleter
 
 pub struct PlayerStart;
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Health
 {
 	pub health: f32,
@@ -534,14 +1095,24 @@ pub struct Health
 
 impl Health
 {
+	pub fn is_immune(&self, damage_type: &DamageType) -> bool
+	{
+		self.immunities.iter().any(|i| i.loose_eq(damage_type))
+	}
+
+	pub fn is_immune_to_effect(&self, effect_type: StatusEffectType) -> bool
+	{
+		effect_type
+			.damage_type()
+			.map(|d| self.is_immune(&d))
+			.unwrap_or(false)
+	}
+
 	pub fn damage(&mut self, damage: Damage, factor: f32) -> bool
 	{
-		for immunity in &self.immunities
+		if self.is_immune(&damage.damage_type)
 		{
-			if immunity.loose_eq(&damage.damage_type)
-			{
-				return false;
-			}
+			return false;
 		}
 
 		let mut amount = damage.amount * factor;
@@ -640,6 +1211,13 @@ pub struct Vehicle
 		Option<Box<dyn Fn(Point3<f32>, f32, &mut hecs::World) -> hecs::Entity + Sync + Send>>,
 	pub saved_health: Option<Health>,
 	pub saved_weapon_set: Option<WeaponSet>,
+	pub entry_radius: f32,
+	// Where the pilot is placed on exit, relative to the vehicle's facing.
+	pub exit_forward: f32,
+	pub exit_side: f32,
+	// Selection order and HUD names.
+	pub weapons: Vec<(WeaponType, String)>,
+	pub sound: String,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -659,6 +1237,24 @@ pub enum ItemType
 
 impl ItemType
 {
+	// Matches the spawn names in the levels.
+	pub fn name(&self) -> &str
+	{
+		match self
+		{
+			ItemType::Shard => "shard",
+			ItemType::Suit => "suit",
+			ItemType::Flask => "flask",
+			ItemType::Heart => "heart",
+			ItemType::BulletAmmo => "bullet_ammo",
+			ItemType::FreezeAmmo => "freeze_ammo",
+			ItemType::OrbAmmo => "orb_ammo",
+			ItemType::ExtraLife => "extra_life",
+			ItemType::FreezeGun => "freeze_gun",
+			ItemType::OrbGun => "orb_gun",
+		}
+	}
+
 	pub fn sprite_sheet(&self) -> &str
 	{
 		match self
@@ -693,3 +1289,129 @@ impl ItemType
 		}
 	}
 }
+
+#[test]
+fn test_ability_readiness()
+{
+	let mut ability = Ability {
+		ability_type: AbilityType::PresentBomb {
+			fuse: 1.,
+			damage: 10.,
+			radius: 10.,
+		},
+		cooldown: 2.,
+		energy_cost: 50.,
+		max_energy: 100.,
+		energy_regen: 10.,
+		energy: 100.,
+		time_to_ready: 0.,
+		active: false,
+		active_until: 0.,
+	};
+	assert!(ability.ready(0.));
+	assert_eq!(ability.readiness(0.), 1.);
+
+	ability.time_to_ready = 2.;
+	assert!(!ability.ready(1.));
+	assert_eq!(ability.readiness(1.), 0.5);
+
+	ability.energy = 10.;
+	assert!(!ability.ready(2.));
+	assert_eq!(ability.readiness(2.), 0.2);
+}
+
+#[test]
+fn test_vehicle_physics()
+{
+	let physics = VehiclePhysics {
+		acceleration: 100.,
+		braking: 200.,
+		turn_rate: 2.,
+		full_turn_speed: 50.,
+		grip: 10.,
+		ice_grip: 1.,
+		drag: 1.,
+		ram_damage: 0.1,
+		min_ram_speed: 50.,
+	};
+	let mut vel = Velocity {
+		vel: Vector3::zeros(),
+		dir_vel: 0.,
+	};
+	let input = |throttle, steer, on_ice| DriveInput {
+		throttle: throttle,
+		steer: steer,
+		on_ice: on_ice,
+	};
+	// Stationary vehicles can't turn.
+	physics.drive(&mut vel, 0., input(0., 1., false), 100., 0.1);
+	assert_eq!(vel.dir_vel, 0.);
+
+	for _ in 0..20
+	{
+		physics.drive(&mut vel, 0., input(1., 0., false), 100., 0.1);
+	}
+	assert!((vel.vel.norm() - 100.).abs() < 1e-3);
+
+	// Coasting slows down.
+	let mut coast_vel = vel;
+	physics.drive(&mut coast_vel, 0., input(0., 0., false), 100., 0.1);
+	assert!(coast_vel.vel.norm() < vel.vel.norm());
+
+	// Turn 90 degrees: ice keeps more of the old velocity sideways.
+	let mut ice_vel = vel;
+	physics.drive(
+		&mut vel,
+		std::f32::consts::FRAC_PI_2,
+		input(0., 1., false),
+		100.,
+		0.1,
+	);
+	physics.drive(
+		&mut ice_vel,
+		std::f32::consts::FRAC_PI_2,
+		input(0., 1., true),
+		100.,
+		0.1,
+	);
+	assert!(ice_vel.vel.norm() > vel.vel.norm());
+	assert!(vel.dir_vel.abs() < 1e-3);
+}
+
+#[test]
+fn test_status_effects()
+{
+	let mut status_effects = StatusEffects::new();
+	let poison = StatusEffect {
+		effect_type: StatusEffectType::Poisoned,
+		strength: 6.,
+		until: 4.,
+	};
+	status_effects.add(poison);
+	status_effects.add(poison);
+	assert_eq!(status_effects.effects.len(), 1);
+	assert_eq!(
+		status_effects
+			.get(StatusEffectType::Poisoned)
+			.unwrap()
+			.strength,
+		10.
+	);
+
+	// Chill slows, then freezes until it thaws back to 1.
+	status_effects.add_chill(0.5, 0.);
+	assert!(status_effects.speed_factor() < 1.);
+	assert!(!status_effects.is_incapacitated());
+	status_effects.add_chill(1., 0.);
+	assert!(status_effects.is_incapacitated());
+	assert_eq!(
+		status_effects.get(StatusEffectType::Frozen).unwrap().until,
+		2.
+	);
+
+	status_effects.update(3.);
+	assert!(!status_effects.is_incapacitated());
+	assert!(status_effects.has(StatusEffectType::Poisoned));
+	status_effects.update(5.);
+	assert!(status_effects.effects.is_empty());
+}
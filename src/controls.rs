@@ -19,6 +19,10 @@ pub enum Action
 	EnterVehicle,
 	PrevWeapon,
 	NextWeapon,
+	UseAbility,
+	NextCutscenePage,
+	SkipCutscene,
+	Screenshot,
 }
 
 impl Action
@@ -40,6 +44,10 @@ impl Action
 			Action::EnterVehicle => "ENTER VEHICLE",
 			Action::PrevWeapon => "PREVIOUS WEAPON",
 			Action::NextWeapon => "NEXT WEAPON",
+			Action::UseAbility => "USE ABILITY",
+			Action::NextCutscenePage => "NEXT CUTSCENE PAGE",
+			Action::SkipCutscene => "SKIP CUTSCENE",
+			Action::Screenshot => "SCREENSHOT",
 		}
 	}
 }
@@ -541,6 +549,25 @@ impl Controls
 		);
 		action_to_inputs.insert(Action::PrevWeapon, [Some(Input::MouseZNeg), None]);
 		action_to_inputs.insert(Action::NextWeapon, [Some(Input::MouseZPos), None]);
+		action_to_inputs.insert(
+			Action::UseAbility,
+			[
+				Some(Input::Keyboard(allegro::KeyCode::Q)),
+				Some(Input::MouseButton(2)),
+			],
+		);
+		action_to_inputs.insert(
+			Action::NextCutscenePage,
+			[Some(Input::Keyboard(allegro::KeyCode::Tab)), None],
+		);
+		action_to_inputs.insert(
+			Action::SkipCutscene,
+			[Some(Input::Keyboard(allegro::KeyCode::Enter)), None],
+		);
+		action_to_inputs.insert(
+			Action::Screenshot,
+			[Some(Input::Keyboard(allegro::KeyCode::F12)), None],
+		);
 
 		Self {
 			action_to_inputs: action_to_inputs,
@@ -548,6 +575,15 @@ impl Controls
 		}
 	}
 
+	// Bind actions added since the controls were saved.
+	pub fn add_missing_actions(&mut self)
+	{
+		for (action, inputs) in Controls::new().action_to_inputs
+		{
+			self.action_to_inputs.entry(action).or_insert(inputs);
+		}
+	}
+
 	pub fn get_action_string(&self, action: Action) -> String
 	{
 		let mut inputs = vec![];
@@ -568,16 +604,19 @@ pub struct ControlsHandler
 	controls: Controls,
 	input_to_action: BTreeMap<Input, Action>,
 	input_state: HashMap<Input, InputState>,
+	frozen: bool,
 }
 
 impl ControlsHandler
 {
-	pub fn new(controls: Controls) -> Self
+	pub fn new(mut controls: Controls) -> Self
 	{
+		controls.add_missing_actions();
 		let mut ret = Self {
 			controls: controls,
 			input_to_action: BTreeMap::new(),
 			input_state: HashMap::new(),
+			frozen: false,
 		};
 		ret.update_derived();
 		ret
@@ -727,8 +766,22 @@ impl ControlsHandler
 		vec![]
 	}
 
+	// While frozen, only the screenshot and cutscene actions work.
+	pub fn set_frozen(&mut self, frozen: bool)
+	{
+		self.frozen = frozen;
+	}
+
 	pub fn get_action_state(&mut self, action: Action) -> f32
 	{
+		if self.frozen
+			&& !matches!(
+				action,
+				Action::Screenshot | Action::NextCutscenePage | Action::SkipCutscene
+			)
+		{
+			return 0.;
+		}
 		let mut ret = 0.;
 		for inputs in &self.controls.action_to_inputs.get(&action)
 		{
@@ -0,0 +1,143 @@
+use crate::error::Result;
+use crate::{game_state, utils};
+
+use allegro::*;
+use allegro_font::*;
+use serde_derive::{Deserialize, Serialize};
+
+// Pixels per second.
+const SCROLL_SPEED: f64 = 48.;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreditsSection
+{
+	pub title: String,
+	pub entries: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreditsDesc
+{
+	#[serde(default)]
+	pub music: String,
+	pub sections: Vec<CreditsSection>,
+}
+
+pub struct Credits
+{
+	display_width: f32,
+	display_height: f32,
+	// Text and whether it's a title.
+	lines: Vec<(String, bool)>,
+	start_time: f64,
+}
+
+impl Credits
+{
+	pub fn new(
+		state: &mut game_state::GameState, display_width: f32, display_height: f32,
+	) -> Result<Self>
+	{
+		let desc: CreditsDesc = utils::load_config("data/credits.cfg")?;
+		if state.options.play_music && !desc.music.is_empty()
+		{
+			state.sfx.set_playlist(vec![desc.music.clone()]);
+			state.sfx.play_music()?;
+			state.sfx.set_combat_music(None)?;
+		}
+		state.hide_mouse = true;
+		state.paused = false;
+
+		let mut lines = vec![];
+		for section in desc.sections
+		{
+			lines.push((section.title, true));
+			for entry in section.entries
+			{
+				// The UI font has no lowercase letters.
+				lines.push((entry.to_uppercase(), false));
+			}
+			lines.push(("".to_string(), false));
+		}
+
+		Ok(Self {
+			display_width: display_width,
+			display_height: display_height,
+			lines: lines,
+			start_time: state.time(),
+		})
+	}
+
+	pub fn resize(&mut self, display_width: f32, display_height: f32)
+	{
+		self.display_width = display_width;
+		self.display_height = display_height;
+	}
+
+	fn scroll(&self, state: &game_state::GameState) -> f32
+	{
+		((state.time() - self.start_time) * SCROLL_SPEED) as f32
+	}
+
+	pub fn input(
+		&mut self, event: &Event, state: &mut game_state::GameState,
+	) -> Result<Option<game_state::NextScreen>>
+	{
+		state.controls.decode_event(event);
+		match event
+		{
+			Event::KeyDown {
+				keycode: KeyCode::Escape | KeyCode::Enter | KeyCode::Space,
+				..
+			}
+			| Event::MouseButtonDown { .. } => Ok(Some(game_state::NextScreen::Menu)),
+			_ => Ok(None),
+		}
+	}
+
+	pub fn logic(
+		&mut self, state: &mut game_state::GameState,
+	) -> Result<Option<game_state::NextScreen>>
+	{
+		let lh = state.ui_font.get_line_height() as f32;
+		if self.scroll(state) > self.display_height + self.lines.len() as f32 * lh
+		{
+			return Ok(Some(game_state::NextScreen::Menu));
+		}
+		Ok(None)
+	}
+
+	pub fn draw(&mut self, state: &game_state::GameState) -> Result<()>
+	{
+		utils::use_2d_projection(&state.core, self.display_width, self.display_height);
+		state.core.clear_to_color(Color::from_rgb_f(0., 0., 0.));
+
+		let lh = state.ui_font.get_line_height() as f32;
+		let mut y = (self.display_height - self.scroll(state)).floor();
+		for (text, title) in &self.lines
+		{
+			if y > -lh && y < self.display_height
+			{
+				let color = if *title
+				{
+					Color::from_rgb_f(0.8, 0.8, 0.5)
+				}
+				else
+				{
+					Color::from_rgb_f(1., 1., 1.)
+				};
+				state.core.draw_text(
+					&state.ui_font,
+					color,
+					self.display_width / 2.,
+					y,
+					FontAlign::Centre,
+					text,
+				);
+			}
+			y += lh;
+		}
+
+		Ok(())
+	}
+}
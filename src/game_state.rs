@@ -1,5 +1,5 @@
 use crate::error::Result;
-use crate::sfx::Sfx;
+use crate::sfx::{Sfx, SoundCategory};
 use crate::utils::{load_bitmap, Vec2D, DT};
 use crate::{atlas, character_sprite_sheet, components, controls, utils};
 use allegro::*;
@@ -14,16 +14,69 @@ use std::{fmt, path};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode
+{
+	ThirdPerson,
+	FirstPerson,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RenderMode
+{
+	Fixed,
+	Native,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScalingMode
+{
+	Nearest,
+	Integer,
+	Smooth,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PostProcess
+{
+	Off,
+	Crt,
+	RetroPalette,
+	Colorblind,
+	Custom,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct Options
 {
 	pub fullscreen: bool,
+	pub exclusive_fullscreen: bool,
 	pub width: i32,
 	pub height: i32,
 	pub play_music: bool,
 	pub vsync_method: i32,
+	pub render_mode: RenderMode,
+	pub render_width: i32,
+	pub render_height: i32,
+	pub scaling_mode: ScalingMode,
+	pub post_process: PostProcess,
+	pub custom_post_process: String,
+	// Save every Nth rendered frame, 0 to disable.
+	pub frame_dump_interval: i32,
 	pub sfx_volume: f32,
 	pub music_volume: f32,
+	pub weapons_volume: f32,
+	pub monsters_volume: f32,
+	pub ui_volume: f32,
+	pub ambience_volume: f32,
+	pub voice_volume: f32,
+	pub captions: bool,
+	pub camera_mode: CameraMode,
+	// Vertical, in degrees.
+	pub fov: f32,
+	pub screen_shake: f32,
+	pub camera_smoothing: bool,
 	pub controls: controls::Controls,
 	pub unlocked: HashSet<String>,
 }
@@ -36,47 +89,331 @@ impl Default for Options
 		unlocked.insert("ARCTIC APOCALYPSE".to_string());
 		Self {
 			fullscreen: true,
+			exclusive_fullscreen: false,
 			width: 1024,
 			height: 728,
 			play_music: true,
 			vsync_method: 2,
+			render_mode: RenderMode::Fixed,
+			render_width: 800,
+			render_height: 600,
+			scaling_mode: ScalingMode::Nearest,
+			post_process: PostProcess::Off,
+			custom_post_process: "data/custom_post_process.glsl".into(),
+			frame_dump_interval: 0,
 			sfx_volume: 1.,
 			music_volume: 1.,
+			weapons_volume: 1.,
+			monsters_volume: 1.,
+			ui_volume: 1.,
+			ambience_volume: 1.,
+			voice_volume: 1.,
+			captions: false,
+			camera_mode: CameraMode::ThirdPerson,
+			fov: 90.,
+			screen_shake: 1.,
+			camera_smoothing: true,
 			controls: controls::Controls::new(),
 			unlocked: unlocked,
 		}
 	}
 }
 
+impl Options
+{
+	pub fn category_volume(&self, category: SoundCategory) -> f32
+	{
+		match category
+		{
+			SoundCategory::Weapons => self.weapons_volume,
+			SoundCategory::Monsters => self.monsters_volume,
+			SoundCategory::Ui => self.ui_volume,
+			SoundCategory::Ambience => self.ambience_volume,
+			SoundCategory::Voice => self.voice_volume,
+		}
+	}
+
+	pub fn set_category_volume(&mut self, category: SoundCategory, volume: f32)
+	{
+		match category
+		{
+			SoundCategory::Weapons => self.weapons_volume = volume,
+			SoundCategory::Monsters => self.monsters_volume = volume,
+			SoundCategory::Ui => self.ui_volume = volume,
+			SoundCategory::Ambience => self.ambience_volume = volume,
+			SoundCategory::Voice => self.voice_volume = volume,
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
-pub struct LevelEntry
+#[serde(default)]
+pub struct CarryOver
+{
+	pub health: bool,
+	pub weapons: bool,
+	pub lives: bool,
+}
+
+impl Default for CarryOver
+{
+	fn default() -> Self
+	{
+		Self {
+			health: true,
+			weapons: true,
+			lives: true,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CampaignExit
+{
+	pub name: String,
+	pub level: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CampaignLevel
 {
 	pub filename: String,
 	pub name: String,
+	// Defaults to the following level in the episode.
+	#[serde(default)]
+	pub next: String,
+	// Named exits, e.g. secret ones, selected by the `next_level` TMX property.
+	#[serde(default)]
+	pub exits: Vec<CampaignExit>,
+	// What carries over when entering this level, overriding the campaign default.
+	#[serde(default)]
+	pub carry_over: Option<CarryOver>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
-pub struct Levels
+pub struct Episode
 {
-	pub levels: Vec<LevelEntry>,
+	pub name: String,
+	pub levels: Vec<CampaignLevel>,
+	// Slideshows shown before the first level and after the last one.
+	#[serde(default)]
+	pub intro: String,
+	#[serde(default)]
+	pub ending: String,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum PlayerClass
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Campaign
 {
-	Santa,
-	Reindeer,
+	pub episodes: Vec<Episode>,
+	#[serde(default)]
+	pub carry_over: CarryOver,
+}
+
+impl Campaign
+{
+	pub fn levels(&self) -> impl Iterator<Item = &CampaignLevel>
+	{
+		self.episodes.iter().flat_map(|e| e.levels.iter())
+	}
+
+	pub fn find_level(&self, filename: &str) -> Option<&CampaignLevel>
+	{
+		self.levels().find(|l| l.filename == filename)
+	}
+
+	pub fn find_episode(&self, filename: &str) -> Option<&Episode>
+	{
+		self.episodes
+			.iter()
+			.find(|e| e.levels.iter().any(|l| l.filename == filename))
+	}
+
+	// The intro only plays when starting an episode from its first level.
+	pub fn intro(&self, filename: &str) -> Option<&str>
+	{
+		self.episodes
+			.iter()
+			.find(|e| e.levels.first().map(|l| l.filename == filename) == Some(true))
+			.map(|e| e.intro.as_str())
+			.filter(|intro| !intro.is_empty())
+	}
+
+	// Returns None when the episode is over.
+	pub fn next_level(&self, cur_level: &str, exit: &str) -> Option<String>
+	{
+		for episode in &self.episodes
+		{
+			for (i, level) in episode.levels.iter().enumerate()
+			{
+				if level.filename != cur_level
+				{
+					continue;
+				}
+				if !exit.is_empty()
+				{
+					if let Some(exit) = level.exits.iter().find(|e| e.name == exit)
+					{
+						return Some(exit.level.clone());
+					}
+					// Fall back to the usual next level.
+					println!("Unknown exit '{}' in {}", exit, cur_level);
+				}
+				if !level.next.is_empty()
+				{
+					return Some(level.next.clone());
+				}
+				else
+				{
+					return episode.levels.get(i + 1).map(|l| l.filename.clone());
+				}
+			}
+		}
+		// Levels outside the campaign can still name the next level directly.
+		if exit.is_empty()
+		{
+			None
+		}
+		else
+		{
+			Some(exit.to_string())
+		}
+	}
+
+	pub fn carry_over(&self, level: &str) -> &CarryOver
+	{
+		self.find_level(level)
+			.and_then(|l| l.carry_over.as_ref())
+			.unwrap_or(&self.carry_over)
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerClass
+{
+	pub name: String,
+	pub sprite_sheet: String,
+	pub corpse_sprite_sheet: String,
+	pub death_sound: String,
+	pub speed: f32,
+	pub can_strafe: bool,
+	pub health: f32,
+	// Goes into the SantaGun slot, which is fed by ammo regen and bullet ammo pickups.
+	pub weapon: components::Weapon,
+	pub ammo_name: String,
+	pub ammo_regen: i32,
+	pub ammo_pickup: i32,
+	#[serde(default)]
+	pub ability: Option<components::Ability>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerClasses
+{
+	pub classes: Vec<PlayerClass>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VehicleWeapon
+{
+	pub ammo_name: String,
+	pub weapon: components::Weapon,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VehicleType
+{
+	// Used as the spawn name in levels and scripts.
+	pub name: String,
+	pub sprite_sheet: String,
+	pub size: f32,
+	pub mass: f32,
+	pub health: f32,
+	pub armour: f32,
+	pub speed: f32,
+	pub can_strafe: bool,
+	pub physics: components::VehiclePhysics,
+	pub weapons: Vec<VehicleWeapon>,
+	pub entry_radius: f32,
+	pub exit_forward: f32,
+	pub exit_side: f32,
+	pub sound: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VehicleTypes
+{
+	pub vehicles: Vec<VehicleType>,
+}
+
+pub const STARTING_LIVES: i32 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CampaignState
+{
+	pub level: String,
+	// Name of the PlayerClass.
+	pub player_class: String,
+	pub health: Option<components::Health>,
+	pub weapon_set: Option<components::WeaponSet>,
+	pub lives: i32,
+}
+
+impl CampaignState
+{
+	pub fn new(level: &str, player_class: &str) -> Self
+	{
+		Self {
+			level: level.to_string(),
+			player_class: player_class.to_string(),
+			health: None,
+			weapon_set: None,
+			lives: STARTING_LIVES,
+		}
+	}
+
+	pub fn advance(
+		&self, campaign: &Campaign, next_level: &str, health: components::Health,
+		weapon_set: components::WeaponSet, lives: i32,
+	) -> Self
+	{
+		let carry_over = campaign.carry_over(next_level);
+		Self {
+			level: next_level.to_string(),
+			player_class: self.player_class.clone(),
+			health: if carry_over.health
+			{
+				Some(health)
+			}
+			else
+			{
+				None
+			},
+			weapon_set: if carry_over.weapons
+			{
+				Some(weapon_set)
+			}
+			else
+			{
+				None
+			},
+			lives: if carry_over.lives
+			{
+				lives
+			}
+			else
+			{
+				STARTING_LIVES
+			},
+		}
+	}
 }
 
 pub enum NextScreen
 {
-	Game(
-		String,
-		PlayerClass,
-		Option<components::Health>,
-		Option<components::WeaponSet>,
-		i32,
-	),
+	Game(CampaignState),
+	Slideshow(String, Box<NextScreen>),
+	Credits,
 	Menu,
 	Quit,
 }
@@ -135,7 +472,11 @@ pub struct GameState
 	pub draw_scale: f32,
 	pub display_width: f32,
 	pub display_height: f32,
-	pub levels: Levels,
+	pub buffer_width: f32,
+	pub buffer_height: f32,
+	pub campaign: Campaign,
+	pub player_classes: PlayerClasses,
+	pub vehicle_types: VehicleTypes,
 	pub controls: controls::ControlsHandler,
 
 	bitmaps: HashMap<String, Bitmap>,
@@ -160,7 +501,11 @@ impl GameState
 		core.install_mouse()
 			.map_err(|_| "Couldn't install mouse".to_string())?;
 
-		let sfx = Sfx::new(options.sfx_volume, options.music_volume, &core)?;
+		let mut sfx = Sfx::new(options.sfx_volume, options.music_volume, &core)?;
+		for &category in &SoundCategory::all()
+		{
+			sfx.set_category_volume(category, options.category_volume(category));
+		}
 
 		let ui_font = ttf
 			.load_ttf_font("data/GAMEPLAY-1987.ttf", 16, TtfFlags::zero())
@@ -169,7 +514,9 @@ impl GameState
 			.load_ttf_font("data/Open 24 Display St.ttf", -48, TtfFlags::zero())
 			.map_err(|_| "Couldn't load 'data/Open 24 Display St.ttf'".to_string())?;
 
-		let levels: Levels = utils::load_config("data/levels.cfg")?;
+		let campaign: Campaign = utils::load_config("data/campaign.cfg")?;
+		let player_classes: PlayerClasses = utils::load_config("data/player_classes.cfg")?;
+		let vehicle_types: VehicleTypes = utils::load_config("data/vehicles.cfg")?;
 		let controls = controls::ControlsHandler::new(options.controls.clone());
 
 		Ok(GameState {
@@ -191,21 +538,50 @@ impl GameState
 			draw_scale: 1.,
 			display_width: 0.,
 			display_height: 0.,
-			levels: levels,
+			buffer_width: 800.,
+			buffer_height: 600.,
+			campaign: campaign,
+			player_classes: player_classes,
+			vehicle_types: vehicle_types,
 			controls: controls,
 		})
 	}
 
 	pub fn transform_mouse(&self, x: f32, y: f32) -> (f32, f32)
 	{
-		let bw = 800.;
-		let bh = 600.;
+		let bw = self.buffer_width;
+		let bh = self.buffer_height;
 
 		let x = (x - self.display_width / 2.) / self.draw_scale + bw / 2.;
 		let y = (y - self.display_height / 2.) / self.draw_scale + bh / 2.;
 		(x, y)
 	}
 
+	pub fn buffer_size(&self, display_width: i32, display_height: i32) -> (i32, i32)
+	{
+		match self.options.render_mode
+		{
+			RenderMode::Fixed => (
+				utils::max(self.options.render_width, 320),
+				utils::max(self.options.render_height, 240),
+			),
+			RenderMode::Native => (display_width, display_height),
+		}
+	}
+
+	pub fn update_draw_scale(&mut self)
+	{
+		let draw_scale = utils::min(
+			self.display_width / self.buffer_width,
+			self.display_height / self.buffer_height,
+		);
+		self.draw_scale = match self.options.scaling_mode
+		{
+			ScalingMode::Integer => utils::max(1., draw_scale.floor()),
+			ScalingMode::Nearest | ScalingMode::Smooth => draw_scale,
+		};
+	}
+
 	pub fn cache_bitmap<'l>(&'l mut self, name: &str) -> Result<&'l Bitmap>
 	{
 		Ok(match self.bitmaps.entry(name.to_string())
@@ -242,8 +618,61 @@ impl GameState
 		self.character_sheets.get(name)
 	}
 
+	pub fn player_class(&self, name: &str) -> Result<&PlayerClass>
+	{
+		self.player_classes
+			.classes
+			.iter()
+			.find(|c| c.name == name)
+			.ok_or_else(|| format!("Unknown player class: {}", name).into())
+	}
+
+	pub fn vehicle_type(&self, name: &str) -> Option<&VehicleType>
+	{
+		self.vehicle_types.vehicles.iter().find(|v| v.name == name)
+	}
+
 	pub fn time(&self) -> f64
 	{
 		self.tick as f64 * DT as f64
 	}
 }
+
+#[test]
+fn test_campaign_next_level()
+{
+	let level = |filename: &str, exits: Vec<CampaignExit>| CampaignLevel {
+		filename: filename.to_string(),
+		name: filename.to_string(),
+		next: "".to_string(),
+		exits: exits,
+		carry_over: None,
+	};
+	let campaign = Campaign {
+		episodes: vec![Episode {
+			name: "EPISODE".to_string(),
+			intro: "intro".to_string(),
+			ending: "".to_string(),
+			levels: vec![
+				level(
+					"a",
+					vec![CampaignExit {
+						name: "secret".to_string(),
+						level: "c".to_string(),
+					}],
+				),
+				level("b", vec![]),
+				level("c", vec![]),
+			],
+		}],
+		carry_over: CarryOver::default(),
+	};
+	assert_eq!(campaign.next_level("a", ""), Some("b".to_string()));
+	assert_eq!(campaign.next_level("a", "secret"), Some("c".to_string()));
+	assert_eq!(campaign.next_level("a", "unknown"), Some("b".to_string()));
+	assert_eq!(campaign.next_level("b", ""), Some("c".to_string()));
+	assert_eq!(campaign.next_level("c", ""), None);
+	assert_eq!(campaign.next_level("other", "d"), Some("d".to_string()));
+	assert_eq!(campaign.intro("a"), Some("intro"));
+	assert_eq!(campaign.intro("b"), None);
+}
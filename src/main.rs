@@ -3,17 +3,24 @@
 #![allow(dead_code)]
 
 mod atlas;
+mod capture;
 mod character_sprite_sheet;
 mod components;
 mod controls;
+mod credits;
 mod error;
 mod game_state;
 mod map;
 mod menu;
+mod messages;
+mod post_process;
+mod script;
 mod sfx;
+mod slideshow;
 mod spatial_grid;
 mod ui;
 mod utils;
+mod video;
 
 use crate::error::Result;
 use crate::game_state::{GameState, NextScreen};
@@ -29,17 +36,120 @@ enum CurScreen
 {
 	Game(map::Map),
 	Menu(menu::Menu),
+	Slideshow(slideshow::Slideshow),
+	Credits(credits::Credits),
 }
 
-fn real_main() -> Result<()>
+impl CurScreen
 {
-	let mut state = GameState::new()?;
+	fn resize(&mut self, display_width: f32, display_height: f32)
+	{
+		match self
+		{
+			CurScreen::Game(map) => map.resize(display_width, display_height),
+			CurScreen::Menu(menu) => menu.resize(display_width, display_height),
+			CurScreen::Slideshow(slideshow) => slideshow.resize(display_width, display_height),
+			CurScreen::Credits(credits) => credits.resize(display_width, display_height),
+		}
+	}
+
+	fn display_failed(&mut self, state: &mut GameState, error: &str)
+	{
+		match self
+		{
+			CurScreen::Game(map) => map.display_failed(state, error),
+			CurScreen::Menu(menu) => menu.display_failed(state, error),
+			_ => (),
+		}
+	}
+}
+
+fn create_buffer(state: &mut GameState, display: &Display) -> Result<Bitmap>
+{
+	let (buffer_width, buffer_height) =
+		state.buffer_size(display.get_width(), display.get_height());
+
+	let old_flags = state.core.get_new_bitmap_flags();
+	if state.options.scaling_mode == game_state::ScalingMode::Smooth
+	{
+		state
+			.core
+			.set_new_bitmap_flags(old_flags | MIN_LINEAR | MAG_LINEAR);
+	}
+	state.core.set_new_bitmap_depth(16);
+	let buffer = Bitmap::new(&state.core, buffer_width, buffer_height)
+		.map_err(|_| format!("Couldn't create {}x{} buffer", buffer_width, buffer_height));
+	state.core.set_new_bitmap_depth(0);
+	state.core.set_new_bitmap_flags(old_flags);
+	let buffer = buffer?;
+
+	state.display_width = display.get_width() as f32;
+	state.display_height = display.get_height() as f32;
+	state.buffer_width = buffer_width as f32;
+	state.buffer_height = buffer_height as f32;
+	state.update_draw_scale();
+	Ok(buffer)
+}
 
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct DisplaySettings
+{
+	fullscreen: bool,
+	exclusive_fullscreen: bool,
+	width: i32,
+	height: i32,
+	vsync_method: i32,
+}
+
+impl DisplaySettings
+{
+	fn new(options: &game_state::Options) -> Self
+	{
+		Self {
+			fullscreen: options.fullscreen,
+			exclusive_fullscreen: options.exclusive_fullscreen,
+			width: options.width,
+			height: options.height,
+			vsync_method: options.vsync_method,
+		}
+	}
+
+	fn apply(&self, options: &mut game_state::Options)
+	{
+		options.fullscreen = self.fullscreen;
+		options.exclusive_fullscreen = self.exclusive_fullscreen;
+		options.width = self.width;
+		options.height = self.height;
+		options.vsync_method = self.vsync_method;
+	}
+
+	// Toggling a fullscreen window doesn't need a new display.
+	fn needs_new_display(&self, old: &DisplaySettings) -> bool
+	{
+		if self.exclusive_fullscreen || old.exclusive_fullscreen
+		{
+			return self != old;
+		}
+		self.width != old.width
+			|| self.height != old.height
+			|| self.vsync_method != old.vsync_method
+	}
+}
+
+fn create_display(state: &GameState) -> Result<Display>
+{
 	let mut flags = OPENGL | RESIZABLE;
 
 	if state.options.fullscreen
 	{
-		flags = flags | FULLSCREEN_WINDOW;
+		if state.options.exclusive_fullscreen
+		{
+			flags = OPENGL | FULLSCREEN;
+		}
+		else
+		{
+			flags = flags | FULLSCREEN_WINDOW;
+		}
 	}
 	state.core.set_new_display_flags(flags);
 
@@ -48,28 +158,39 @@ fn real_main() -> Result<()>
 		16,
 		DisplayOptionImportance::Suggest,
 	);
-	if state.options.vsync_method == 1
-	{
-		state.core.set_new_display_option(
-			DisplayOption::Vsync,
-			1,
-			DisplayOptionImportance::Suggest,
-		);
-	}
-	let display = Display::new(&state.core, state.options.width, state.options.height)
-		.map_err(|_| "Couldn't create display".to_string())?;
+	state.core.set_new_display_option(
+		DisplayOption::Vsync,
+		if state.options.vsync_method == 1
+		{
+			1
+		}
+		else
+		{
+			0
+		},
+		DisplayOptionImportance::Suggest,
+	);
+	Display::new(&state.core, state.options.width, state.options.height).map_err(|_| {
+		format!(
+			"Couldn't create {}x{} display",
+			state.options.width, state.options.height
+		)
+		.into()
+	})
+}
 
-	let buffer_width = 800;
-	let buffer_height = 600;
-	state.core.set_new_bitmap_depth(16);
-	let buffer = Bitmap::new(&state.core, buffer_width, buffer_height).unwrap();
-	state.core.set_new_bitmap_depth(0);
+fn real_main() -> Result<()>
+{
+	let mut state = GameState::new()?;
 
-	state.display_width = display.get_width() as f32;
-	state.display_height = display.get_height() as f32;
-	state.draw_scale = utils::min(
-		(display.get_width() as f32) / (buffer.get_width() as f32),
-		(display.get_height() as f32) / (buffer.get_height() as f32),
+	let mut display = create_display(&state)?;
+
+	let mut buffer = create_buffer(&mut state, &display)?;
+	let mut old_scaling_mode = state.options.scaling_mode;
+	let mut post_processor = post_process::PostProcessor::new(&state, &mut display);
+	let mut old_post_process = (
+		state.options.post_process,
+		state.options.custom_post_process.clone(),
 	);
 
 	gl_loader::init_gl();
@@ -99,15 +220,14 @@ fn real_main() -> Result<()>
 	let mut draw = true;
 	//~ let mut rng = thread_rng();
 
-	let mut cur_screen = CurScreen::Menu(menu::Menu::new(
-		&mut state,
-		buffer_width as f32,
-		buffer_height as f32,
-	)?);
+	let (buffer_width, buffer_height) = (state.buffer_width, state.buffer_height);
+	let mut cur_screen = CurScreen::Menu(menu::Menu::new(&mut state, buffer_width, buffer_height)?);
 
 	let mut logics_without_draw = 0;
+	let mut take_screenshot = false;
+	let mut frame_dumper = capture::FrameDumper::new();
 	let mut old_mouse_hide = state.hide_mouse;
-	let mut old_fullscreen = state.options.fullscreen;
+	let mut old_display_settings = DisplaySettings::new(&state.options);
 	//~ let mut prev_frame_start = state.core.get_time();
 
 	timer.start();
@@ -115,15 +235,20 @@ fn real_main() -> Result<()>
 	{
 		if draw && queue.is_empty()
 		{
-			if state.display_width != display.get_width() as f32
+			let buffer_size = state.buffer_size(display.get_width(), display.get_height());
+			if buffer_size != (buffer.get_width(), buffer.get_height())
+				|| old_scaling_mode != state.options.scaling_mode
+			{
+				buffer = create_buffer(&mut state, &display)?;
+				old_scaling_mode = state.options.scaling_mode;
+				cur_screen.resize(state.buffer_width, state.buffer_height);
+			}
+			else if state.display_width != display.get_width() as f32
 				|| state.display_height != display.get_height() as f32
 			{
 				state.display_width = display.get_width() as f32;
 				state.display_height = display.get_height() as f32;
-				state.draw_scale = utils::min(
-					(display.get_width() as f32) / (buffer.get_width() as f32),
-					(display.get_height() as f32) / (buffer.get_height() as f32),
-				);
+				state.update_draw_scale();
 			}
 
 			//~ let frame_start = state.core.get_time();
@@ -133,6 +258,24 @@ fn real_main() -> Result<()>
 			{
 				CurScreen::Game(map) => map.draw(&state)?,
 				CurScreen::Menu(menu) => menu.draw(&state)?,
+				CurScreen::Slideshow(slideshow) => slideshow.draw(&state)?,
+				CurScreen::Credits(credits) => credits.draw(&state)?,
+			}
+
+			// Failing to write a capture shouldn't end the game.
+			if take_screenshot
+			{
+				if let Err(e) = capture::save_screenshot(&state.core, &buffer)
+				{
+					println!("Couldn't save screenshot: {}", e);
+				}
+				take_screenshot = false;
+			}
+			if let Err(e) =
+				frame_dumper.dump_frame(&state.core, &buffer, state.options.frame_dump_interval)
+			{
+				println!("Couldn't dump frame, disabling frame dumping: {}", e);
+				state.options.frame_dump_interval = 0;
 			}
 
 			if state.options.vsync_method == 2
@@ -149,17 +292,13 @@ fn real_main() -> Result<()>
 			let dw = display.get_width() as f32;
 			let dh = display.get_height() as f32;
 
-			state.core.draw_scaled_bitmap(
+			post_processor.draw(
+				&state,
 				&buffer,
-				0.,
-				0.,
-				bw,
-				bh,
 				dw / 2. - bw / 2. * state.draw_scale,
 				dh / 2. - bh / 2. * state.draw_scale,
 				bw * state.draw_scale,
 				bh * state.draw_scale,
-				Flag::zero(),
 			);
 
 			state.core.flip_display();
@@ -177,6 +316,8 @@ fn real_main() -> Result<()>
 		{
 			CurScreen::Game(map) => map.input(&event, &mut state)?,
 			CurScreen::Menu(menu) => menu.input(&event, &mut state)?,
+			CurScreen::Slideshow(slideshow) => slideshow.input(&event, &mut state)?,
+			CurScreen::Credits(credits) => credits.input(&event, &mut state)?,
 		};
 
 		match event
@@ -214,10 +355,22 @@ fn real_main() -> Result<()>
 					next_screen = match &mut cur_screen
 					{
 						CurScreen::Game(map) => map.logic(&mut state)?,
+						CurScreen::Credits(credits) => credits.logic(&mut state)?,
 						_ => None,
 					}
 				}
 
+				if state
+					.controls
+					.get_action_state(controls::Action::Screenshot)
+					> 0.5
+				{
+					state
+						.controls
+						.clear_action_state(controls::Action::Screenshot);
+					take_screenshot = true;
+				}
+
 				if old_mouse_hide != state.hide_mouse
 				{
 					old_mouse_hide = state.hide_mouse;
@@ -226,10 +379,52 @@ fn real_main() -> Result<()>
 						.map_err(|_| "Could not hide cursor.".to_string())?;
 				}
 
-				if old_fullscreen != state.options.fullscreen
+				let display_settings = DisplaySettings::new(&state.options);
+				if display_settings.needs_new_display(&old_display_settings)
+				{
+					match create_display(&state)
+					{
+						Ok(new_display) =>
+						{
+							// Keep the new display current while the old one is destroyed.
+							state
+								.core
+								.set_target_bitmap(Some(new_display.get_backbuffer()));
+							display = new_display;
+							video::convert_memory_bitmaps();
+							queue.register_event_source(display.get_event_source());
+							buffer = create_buffer(&mut state, &display)?;
+							old_scaling_mode = state.options.scaling_mode;
+							cur_screen.resize(state.buffer_width, state.buffer_height);
+							gl::load_with(|symbol| gl_loader::get_proc_address(symbol) as *const _);
+							display
+								.show_cursor(!state.hide_mouse)
+								.map_err(|_| "Could not hide cursor.".to_string())?;
+							post_processor = post_process::PostProcessor::new(&state, &mut display);
+							old_display_settings = display_settings;
+						}
+						Err(e) =>
+						{
+							println!("Couldn't apply video settings: {}", e);
+							old_display_settings.apply(&mut state.options);
+							cur_screen.display_failed(&mut state, &e.to_string());
+						}
+					}
+				}
+				else if display_settings != old_display_settings
 				{
 					display.set_flag(FULLSCREEN_WINDOW, state.options.fullscreen);
-					old_fullscreen = state.options.fullscreen;
+					old_display_settings = display_settings;
+				}
+
+				let post_process = (
+					state.options.post_process,
+					state.options.custom_post_process.clone(),
+				);
+				if post_process != old_post_process
+				{
+					post_processor.update(&state, &mut display);
+					old_post_process = post_process;
 				}
 
 				logics_without_draw += 1;
@@ -248,39 +443,46 @@ fn real_main() -> Result<()>
 		{
 			match next_screen
 			{
-				NextScreen::Game(level, class, health, weapons, lives) =>
+				NextScreen::Game(campaign_state) =>
 				{
-					let mut unlock_name = None;
-					for other_level in &mut state.levels.levels
+					if let Some(level) = state.campaign.find_level(&campaign_state.level)
 					{
-						if level == other_level.filename
-						{
-							unlock_name = Some(other_level.name.clone());
-						}
-					}
-					if let Some(unlock_name) = unlock_name
-					{
-						state.options.unlocked.insert(unlock_name);
+						state.options.unlocked.insert(level.name.clone());
 					}
 					game_state::save_options(&state.core, &state.options)?;
+					let (buffer_width, buffer_height) = (state.buffer_width, state.buffer_height);
 					cur_screen = CurScreen::Game(map::Map::new(
 						&mut state,
-						&level,
-						class,
-						health,
-						weapons,
-						lives,
-						buffer_width as f32,
-						buffer_height as f32,
+						campaign_state,
+						buffer_width,
+						buffer_height,
 					)?);
 				}
-				NextScreen::Menu =>
+				NextScreen::Slideshow(filename, next_screen) =>
+				{
+					let (buffer_width, buffer_height) = (state.buffer_width, state.buffer_height);
+					cur_screen = CurScreen::Slideshow(slideshow::Slideshow::new(
+						&mut state,
+						&filename,
+						*next_screen,
+						buffer_width,
+						buffer_height,
+					)?);
+				}
+				NextScreen::Credits =>
 				{
-					cur_screen = CurScreen::Menu(menu::Menu::new(
+					let (buffer_width, buffer_height) = (state.buffer_width, state.buffer_height);
+					cur_screen = CurScreen::Credits(credits::Credits::new(
 						&mut state,
-						buffer_width as f32,
-						buffer_height as f32,
+						buffer_width,
+						buffer_height,
 					)?);
+				}
+				NextScreen::Menu =>
+				{
+					let (buffer_width, buffer_height) = (state.buffer_width, state.buffer_height);
+					cur_screen =
+						CurScreen::Menu(menu::Menu::new(&mut state, buffer_width, buffer_height)?);
 					state.hide_mouse = false;
 				}
 				NextScreen::Quit =>
@@ -1,13 +1,15 @@
 use crate::error::Result;
-use crate::{atlas, components, controls, game_state, spatial_grid, ui, utils};
+use crate::{
+	atlas, components, controls, game_state, messages, script, sfx, spatial_grid, ui, utils,
+};
 
 use allegro::*;
 use allegro_font::*;
 use allegro_primitives::*;
 use allegro_sys::*;
 use na::{
-	Isometry3, Matrix4, Perspective3, Point2, Point3, Quaternion, RealField, Rotation2, Rotation3,
-	Unit, Vector2, Vector3, Vector4,
+	Isometry3, Perspective3, Point2, Point3, Quaternion, RealField, Rotation2, Rotation3, Unit,
+	Vector2, Vector3, Vector4,
 };
 use nalgebra as na;
 use rand::prelude::*;
@@ -18,6 +20,8 @@ use std::path::Path;
 use std::sync::Arc;
 
 pub const TILE: f32 = 64.;
+// Number of attacking monsters needed for full combat music.
+const MAX_MUSIC_ATTACKERS: f32 = 4.;
 
 struct Bucket
 {
@@ -207,6 +211,22 @@ fn get_target_property(
 	})
 }
 
+fn get_color_property(property: &str, obj: &tiled::Object) -> Option<Result<Color>>
+{
+	obj.properties.get(property).map(|p| match p
+	{
+		tiled::PropertyValue::ColorValue(c) =>
+		{
+			Ok(Color::from_rgba(c.red, c.green, c.blue, c.alpha))
+		}
+		other => Err(format!(
+			"Invalid value for '{}' in object {:?}: {:?}",
+			property, obj, other
+		)
+		.into()),
+	})
+}
+
 fn get_string_property(property: &str, obj: &tiled::Object) -> Option<Result<String>>
 {
 	obj.properties.get(property).map(|p| match p
@@ -220,30 +240,196 @@ fn get_string_property(property: &str, obj: &tiled::Object) -> Option<Result<Str
 	})
 }
 
+// Returns the indices of all the properties named like `{prefix}0`, `{prefix}1`, etc, in order.
+// Gaps are allowed, as Tiled keeps unset object properties around.
+fn get_numbered_properties(prefix: &str, obj: &tiled::Object) -> Vec<usize>
+{
+	let mut ret: Vec<_> = obj
+		.properties
+		.keys()
+		.filter_map(|k| k.strip_prefix(prefix).and_then(|i| i.parse::<usize>().ok()))
+		.collect();
+	ret.sort();
+	ret
+}
+
+fn get_target_mode_property(property: &str, obj: &tiled::Object) -> Result<components::TargetMode>
+{
+	match get_string_property(property, obj)
+	{
+		Some(mode) =>
+		{
+			let mode = mode?;
+			Ok(components::TargetMode::from_str(&mode)
+				.ok_or_else(|| format!("Invalid target mode '{}' in object {:?}", mode, obj))?)
+		}
+		None => Ok(components::TargetMode::Toggle),
+	}
+}
+
+fn get_tags_property(property: &str, obj: &tiled::Object) -> Result<Vec<String>>
+{
+	Ok(get_string_property(property, obj)
+		.unwrap_or(Ok("".to_string()))?
+		.split(',')
+		.map(|t| t.trim())
+		.filter(|t| !t.is_empty())
+		.map(|t| t.to_string())
+		.collect())
+}
+
 fn get_targets_property(
+	prefix: &str, obj: &tiled::Object, id_to_name: &HashMap<u32, String>,
+) -> Result<Vec<components::Target>>
+{
+	let mut ret = vec![];
+	for i in get_numbered_properties(prefix, obj)
+	{
+		let property = format!("{}{}", prefix, i);
+		if let Some(name) = get_target_property(&property, obj, id_to_name)
+		{
+			ret.push(components::Target {
+				name: name?,
+				mode: get_target_mode_property(&format!("{}{}_mode", prefix, i), obj)?,
+			});
+		}
+	}
+	let tag_mode = get_target_mode_property(&format!("{}_tags_mode", prefix), obj)?;
+	for tag in get_tags_property(&format!("{}_tags", prefix), obj)?
+	{
+		ret.push(components::Target {
+			name: format!("{}{}", TAG_PREFIX, tag),
+			mode: tag_mode,
+		});
+	}
+	Ok(ret)
+}
+
+fn get_inputs_property(
 	obj: &tiled::Object, id_to_name: &HashMap<u32, String>,
 ) -> Result<Vec<String>>
 {
 	let mut ret = vec![];
-	for i in 0..10
+	for i in get_numbered_properties("input", obj)
 	{
-		let property = format!("target{}", i);
-		let value = get_target_property(&property, obj, id_to_name);
-		if value.is_none()
+		let property = format!("input{}", i);
+		if let Some(name) = get_target_property(&property, obj, id_to_name)
 		{
-			break;
+			ret.push(name?);
 		}
-		ret.push(value.unwrap()?);
+	}
+	for tag in get_tags_property("input_tags", obj)?
+	{
+		ret.push(format!("{}{}", TAG_PREFIX, tag));
 	}
 	Ok(ret)
 }
 
+// Target names starting with this refer to every entity with that tag.
+const TAG_PREFIX: &str = "#";
+
+pub struct NamedEntities
+{
+	names: HashMap<String, hecs::Entity>,
+	tags: HashMap<String, Vec<hecs::Entity>>,
+	entity_names: HashMap<hecs::Entity, String>,
+}
+
+impl NamedEntities
+{
+	pub fn new() -> Self
+	{
+		Self {
+			names: HashMap::new(),
+			tags: HashMap::new(),
+			entity_names: HashMap::new(),
+		}
+	}
+
+	pub fn insert(&mut self, name: String, tags: &[String], entity: hecs::Entity)
+	{
+		self.entity_names.insert(entity, name.clone());
+		self.names.insert(name, entity);
+		for tag in tags
+		{
+			self.tags.entry(tag.clone()).or_insert(vec![]).push(entity);
+		}
+	}
+
+	pub fn name_of(&self, entity: hecs::Entity) -> Option<&String>
+	{
+		self.entity_names.get(&entity)
+	}
+
+	pub fn get(&self, name: &str) -> Vec<hecs::Entity>
+	{
+		if let Some(tag) = name.strip_prefix(TAG_PREFIX)
+		{
+			self.tags.get(tag).cloned().unwrap_or(vec![])
+		}
+		else
+		{
+			self.names.get(name).into_iter().cloned().collect()
+		}
+	}
+}
+
+fn push_targets(
+	targets: &[components::Target], named_entities: &NamedEntities, world: &hecs::World,
+	activate: &mut Vec<(hecs::Entity, components::TargetMode)>,
+)
+{
+	for target in targets
+	{
+		for entity in named_entities.get(&target.name)
+		{
+			if world.contains(entity)
+			{
+				activate.push((entity, target.mode));
+			}
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct LevelDesc
 {
 	level: String,
 	meshes: String,
+	#[serde(default)]
 	music: String,
+	// Played in order after `music`.
+	#[serde(default)]
+	playlist: Vec<String>,
+	// Blended in when monsters are attacking the player.
+	#[serde(default)]
+	combat_music: String,
+	// Rhai script reacting to level events.
+	#[serde(default)]
+	script: String,
+	// Tile ids vehicles drift on.
+	#[serde(default)]
+	ice_tiles: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CutsceneDesc
+{
+	pages: Vec<components::DialoguePage>,
+}
+
+impl LevelDesc
+{
+	fn playlist(&self) -> Vec<String>
+	{
+		let mut playlist = vec![];
+		if !self.music.is_empty()
+		{
+			playlist.push(self.music.clone());
+		}
+		playlist.extend(self.playlist.iter().cloned());
+		playlist
+	}
 }
 
 pub struct Level
@@ -252,13 +438,14 @@ pub struct Level
 	height: i32,
 	tile_meshes: Vec<Mesh>,
 	tiles: Vec<i32>,
+	ice_tiles: Vec<i32>,
 }
 
 impl Level
 {
 	pub fn new(
-		desc: LevelDesc, named_entities: &mut HashMap<String, hecs::Entity>,
-		state: &mut game_state::GameState, world: &mut hecs::World,
+		desc: LevelDesc, named_entities: &mut NamedEntities, state: &mut game_state::GameState,
+		world: &mut hecs::World,
 	) -> Result<Self>
 	{
 		let tile_meshes = load_meshes(&desc.meshes);
@@ -304,15 +491,20 @@ impl Level
 			tiled::LayerType::ObjectLayer(objects) => objects,
 			_ => return Err("Layer 1 must be the object layer!".to_string().into()),
 		};
+		let object_center = |obj: &tiled::Object| {
+			let start = Point3::new(obj.x, 0., obj.y) / tile_width * TILE;
+			let end = Point3::new(obj.x + obj.width, 0., obj.y + obj.height) / tile_width * TILE;
+			(start, end, start + (end - start) / 2.)
+		};
+		let mut id_to_pos = HashMap::new();
 		for obj in objects.objects()
 		{
 			id_to_name.insert(obj.id(), format!("{}|{}", obj.name, obj.id()));
+			id_to_pos.insert(obj.id(), object_center(&obj).2);
 		}
 		for obj in objects.objects()
 		{
-			let start = Point3::new(obj.x, 0., obj.y) / tile_width * TILE;
-			let end = Point3::new(obj.x + obj.width, 0., obj.y + obj.height) / tile_width * TILE;
-			let center = start + (end - start) / 2.;
+			let (start, end, center) = object_center(&obj);
 
 			let entity = match &obj.obj_type[..]
 			{
@@ -322,41 +514,129 @@ impl Level
 					get_bool_property("active", &obj).unwrap_or(Ok(false))?,
 					world,
 				),
-				"area trigger" => spawn_area_trigger(
-					start.xz(),
-					end.xz(),
-					get_targets_property(&obj, &id_to_name)?,
-					get_bool_property("active", &obj).unwrap_or(Ok(true))?,
-					world,
-				),
+				"area trigger" =>
+				{
+					let shape = match &obj.shape
+					{
+						tiled::ObjectShape::Rect { .. } => components::TriggerShape::Rect {
+							start: start.xz(),
+							end: end.xz(),
+						},
+						tiled::ObjectShape::Ellipse { .. } => components::TriggerShape::Ellipse {
+							start: start.xz(),
+							end: end.xz(),
+						},
+						tiled::ObjectShape::Polygon { points } if points.len() >= 3 =>
+						{
+							components::TriggerShape::Polygon(
+								points
+									.iter()
+									.map(|&(x, y)| {
+										Point2::new(obj.x + x, obj.y + y) / tile_width * TILE
+									})
+									.collect(),
+							)
+						}
+						other =>
+						{
+							return Err(format!(
+								"Unsupported area trigger shape {:?} in object {:?}",
+								other, &obj
+							)
+							.into())
+						}
+					};
+					let filter =
+						get_string_property("filter", &obj).unwrap_or(Ok("player".to_string()))?;
+					spawn_area_trigger(
+						shape,
+						components::TriggerFilter::from_str(&filter).ok_or_else(|| {
+							format!("Invalid filter '{}' in object {:?}", filter, &obj)
+						})?,
+						get_targets_property("target", &obj, &id_to_name)?,
+						get_targets_property("exit_target", &obj, &id_to_name)?,
+						if get_bool_property("repeat", &obj).unwrap_or(Ok(false))?
+						{
+							Some(get_float_property("cooldown", &obj).unwrap_or(Ok(0.))? as f64)
+						}
+						else
+						{
+							None
+						},
+						get_bool_property("active", &obj).unwrap_or(Ok(true))?,
+						world,
+					)
+				}
 				"spawner" => spawn_spawner(
 					center,
 					get_float_property("dir", &obj).unwrap_or(Ok(0.))?,
 					&get_target_property("counter", &obj, &id_to_name)
 						.unwrap_or(Ok("".to_string()))?,
 					get_bool_property("active", &obj).unwrap_or(Ok(false))?,
-					str_to_spawn_fn(&get_string_property("spawn", &obj).unwrap_or(Err(
-						format!("Spawner {:?} needs 'spawn' specified.", &obj).into(),
-					))?)?,
+					str_to_spawn_fn(
+						&get_string_property("spawn", &obj).unwrap_or(Err(format!(
+							"Spawner {:?} needs 'spawn' specified.",
+							&obj
+						)
+						.into()))?,
+						state,
+					)?,
 					get_int_property("max_count", &obj).unwrap_or(Ok(1))?,
 					get_float_property("delay", &obj).unwrap_or(Ok(0.1))?,
 					world,
 				),
+				"ambient sound" => spawn_ambient_sound(
+					center,
+					&get_string_property("sound", &obj).unwrap_or(Err(format!(
+						"Ambient sound {:?} needs 'sound' specified.",
+						&obj
+					)
+					.into()))?,
+					get_float_property("volume", &obj).unwrap_or(Ok(1.))?,
+					get_float_property("radius", &obj).unwrap_or(Ok(512.))?,
+					get_bool_property("active", &obj).unwrap_or(Ok(true))?,
+					world,
+				),
 				"counter" => spawn_counter(
 					get_int_property("max_count", &obj).unwrap_or(Err(format!(
 						"Counter {:?} needs 'max_count' specified.",
 						&obj
 					)
 					.into()))?,
-					get_targets_property(&obj, &id_to_name)?,
+					get_targets_property("target", &obj, &id_to_name)?,
+					get_bool_property("repeat", &obj).unwrap_or(Ok(false))?,
 					get_bool_property("active", &obj).unwrap_or(Ok(true))?,
 					world,
 				),
 				"message" => spawn_message(
 					get_string_property("message", &obj).unwrap_or(Ok("".to_string()))?,
+					get_string_property("speaker", &obj).unwrap_or(Ok("".to_string()))?,
+					get_color_property("color", &obj).unwrap_or(Ok(messages::default_color()))?,
+					get_float_property("duration", &obj).unwrap_or(Ok(5.))? as f64,
+					get_bool_property("active", &obj).unwrap_or(Ok(true))?,
+					world,
+				),
+				"objective" => spawn_objective(
+					get_string_property("text", &obj).unwrap_or(Ok("".to_string()))?,
+					get_target_property("counter", &obj, &id_to_name)
+						.unwrap_or(Ok("".to_string()))?,
+					get_bool_property("optional", &obj).unwrap_or(Ok(false))?,
+					get_targets_property("target", &obj, &id_to_name)?,
 					get_bool_property("active", &obj).unwrap_or(Ok(true))?,
 					world,
 				),
+				"music" => spawn_music(
+					get_string_property("music", &obj)
+						.unwrap_or(Err(
+							format!("Music {:?} needs 'music' specified.", &obj).into()
+						))?
+						.split(',')
+						.map(|s| s.trim().to_string())
+						.filter(|s| !s.is_empty())
+						.collect(),
+					get_bool_property("active", &obj).unwrap_or(Ok(false))?,
+					world,
+				),
 				"next_level" => spawn_next_level(
 					get_string_property("next_level", &obj).unwrap_or(Ok("".to_string()))?,
 					get_bool_property("active", &obj).unwrap_or(Ok(false))?,
@@ -364,22 +644,103 @@ impl Level
 				),
 				"trigger" => spawn_trigger(
 					get_float_property("delay", &obj).unwrap_or(Ok(0.))? as f64,
-					get_targets_property(&obj, &id_to_name)?,
+					get_int_property("repeat", &obj).unwrap_or(Ok(1))?,
+					get_targets_property("target", &obj, &id_to_name)?,
 					get_bool_property("active", &obj).unwrap_or(Ok(false))?,
 					state,
 					world,
 				),
+				"relay" => spawn_relay(
+					match &get_string_property("gate", &obj).unwrap_or(Ok("and".to_string()))?[..]
+					{
+						"and" => components::Gate::And,
+						"or" => components::Gate::Or,
+						"not" => components::Gate::Not,
+						other =>
+						{
+							return Err(
+								format!("Invalid gate '{}' in object {:?}", other, &obj).into()
+							)
+						}
+					},
+					get_inputs_property(&obj, &id_to_name)?,
+					get_targets_property("target", &obj, &id_to_name)?,
+					get_bool_property("once", &obj).unwrap_or(Ok(false))?,
+					get_bool_property("active", &obj).unwrap_or(Ok(true))?,
+					world,
+				),
+				"flag" => spawn_flag(
+					get_bool_property("active", &obj).unwrap_or(Ok(false))?,
+					world,
+				),
+				"cutscene" =>
+				{
+					let mut path = vec![];
+					for i in get_numbered_properties("path", &obj)
+					{
+						match obj.properties.get(&format!("path{}", i))
+						{
+							Some(tiled::PropertyValue::ObjectValue(0)) => (),
+							Some(tiled::PropertyValue::ObjectValue(v)) =>
+							{
+								path.push(*id_to_pos.get(v).ok_or_else(|| {
+									format!("Invalid path{} in object {:?}", i, &obj)
+								})?)
+							}
+							other =>
+							{
+								return Err(format!(
+									"Invalid value for 'path{}' in object {:?}: {:?}",
+									i, &obj, other
+								)
+								.into())
+							}
+						}
+					}
+					if path.is_empty()
+					{
+						path.push(center);
+					}
+					let dialogue =
+						get_string_property("dialogue", &obj).unwrap_or(Ok("".to_string()))?;
+					let pages = if dialogue.is_empty()
+					{
+						vec![]
+					}
+					else
+					{
+						utils::load_config::<CutsceneDesc>(&dialogue)?.pages
+					};
+					spawn_cutscene(
+						path,
+						get_target_property("look_at", &obj, &id_to_name)
+							.unwrap_or(Ok("".to_string()))?,
+						get_float_property("duration", &obj).unwrap_or(Ok(0.))? as f64,
+						pages,
+						get_targets_property("target", &obj, &id_to_name)?,
+						get_bool_property("active", &obj).unwrap_or(Ok(false))?,
+						world,
+					)
+				}
+				"camera point" => spawn_camera_point(center, world),
 				"deleter" => spawn_deleter(
-					get_targets_property(&obj, &id_to_name)?,
+					get_targets_property("target", &obj, &id_to_name)?
+						.into_iter()
+						.map(|t| t.name)
+						.collect(),
 					get_bool_property("active", &obj).unwrap_or(Ok(false))?,
 					world,
 				),
 				"object" =>
 				{
-					let spawn_fn =
-						str_to_spawn_fn(&get_string_property("spawn", &obj).unwrap_or(Err(
-							format!("Object {:?} needs 'spawn' specified.", &obj).into(),
-						))?)?;
+					let spawn_fn = str_to_spawn_fn(
+						&get_string_property("spawn", &obj).unwrap_or(Err(format!(
+							"Object {:?} needs 'spawn' specified.",
+							&obj
+						)
+						.into()))?,
+						state,
+					)?;
 					spawn_fn(
 						center,
 						get_float_property("dir", &obj).unwrap_or(Ok(0.))?,
@@ -391,7 +752,32 @@ impl Level
 				}
 				other => return Err(format!("Unknown object type '{}'", other).into()),
 			};
-			named_entities.insert(format!("{}|{}", obj.name, obj.id()), entity);
+			named_entities.insert(
+				format!("{}|{}", obj.name, obj.id()),
+				&get_tags_property("tags", &obj)?,
+				entity,
+			);
+		}
+
+		for (_, objective) in world.query::<&components::Objective>().iter()
+		{
+			if objective.counter.is_empty()
+			{
+				continue;
+			}
+			let entities = named_entities.get(&objective.counter);
+			if entities.is_empty()
+				|| !entities.iter().all(|&entity| {
+					world.get::<&components::Counter>(entity).is_ok()
+						|| world.get::<&components::Active>(entity).is_ok()
+				})
+			{
+				return Err(format!(
+					"Objective '{}' refers to unknown counter '{}'",
+					objective.text, objective.counter
+				)
+				.into());
+			}
 		}
 
 		Ok(Level {
@@ -399,6 +785,7 @@ impl Level
 			height: height as i32,
 			tile_meshes: tile_meshes_vec,
 			tiles: tiles,
+			ice_tiles: desc.ice_tiles.clone(),
 		})
 	}
 
@@ -448,6 +835,18 @@ impl Level
 		tile <= 2 || tile >= 24
 	}
 
+	pub fn is_ice(&self, loc: Point3<f32>) -> bool
+	{
+		let map_x = (loc.x / TILE).floor() as i32;
+		let map_z = (loc.z / TILE).floor() as i32;
+		if map_x < 0 || map_x >= self.width || map_z < 0 || map_z >= self.height
+		{
+			return false;
+		}
+		self.ice_tiles
+			.contains(&self.tiles[(map_z * self.width + map_x) as usize])
+	}
+
 	pub fn check_collision(&self, loc: Point3<f32>, size: f32) -> Option<Vector3<f32>>
 	{
 		let center_x = (loc.x / TILE).floor() as i32;
@@ -599,6 +998,7 @@ pub fn spawn_projectile(
 					damage: components::Damage {
 						amount: 6.,
 						damage_type: components::DamageType::Regular,
+						effect: None,
 					},
 				},
 			],
@@ -655,6 +1055,7 @@ pub fn spawn_orb_shard(
 					damage: components::Damage {
 						amount: 8.,
 						damage_type: components::DamageType::Regular,
+						effect: None,
 					},
 				},
 			],
@@ -722,6 +1123,7 @@ pub fn spawn_rocket(
 					damage: components::Damage {
 						amount: 24.,
 						damage_type: components::DamageType::Regular,
+						effect: None,
 					},
 					radius: TILE,
 					push_strength: 100.,
@@ -793,6 +1195,7 @@ pub fn spawn_big_rocket(
 					damage: components::Damage {
 						amount: 50.,
 						damage_type: components::DamageType::Flame,
+						effect: None,
 					},
 					radius: TILE,
 					push_strength: 100.,
@@ -864,6 +1267,7 @@ pub fn spawn_snowball(
 					damage: components::Damage {
 						amount: 40.,
 						damage_type: components::DamageType::Cold(0.9),
+						effect: None,
 					},
 					radius: TILE,
 					push_strength: 100.,
@@ -919,6 +1323,7 @@ pub fn spawn_big_snowball(
 					damage: components::Damage {
 						amount: 50.,
 						damage_type: components::DamageType::Cold(0.9),
+						effect: None,
 					},
 					radius: TILE,
 					push_strength: 100.,
@@ -965,6 +1370,11 @@ pub fn spawn_flame(
 				damage_rate: components::Damage {
 					amount: 20.,
 					damage_type: components::DamageType::Flame,
+					effect: Some(components::HitEffect {
+						effect_type: components::StatusEffectType::Burning,
+						strength: 5.,
+						duration: 2.,
+					}),
 				},
 			}],
 		},
@@ -1004,6 +1414,11 @@ pub fn spawn_big_flame(
 				damage_rate: components::Damage {
 					amount: 30.,
 					damage_type: components::DamageType::Flame,
+					effect: Some(components::HitEffect {
+						effect_type: components::StatusEffectType::Burning,
+						strength: 5.,
+						duration: 2.,
+					}),
 				},
 			}],
 		},
@@ -1043,6 +1458,51 @@ pub fn spawn_freeze(
 				damage_rate: components::Damage {
 					amount: 20.,
 					damage_type: components::DamageType::Cold(1.),
+					effect: None,
+				},
+			}],
+		},
+	))
+}
+
+pub fn spawn_poison_cloud(
+	pos: Point3<f32>, state: &mut game_state::GameState, world: &mut hecs::World,
+) -> hecs::Entity
+{
+	let size = TILE / 4.;
+	world.spawn((
+		components::Position { pos: pos, dir: 0. },
+		components::Velocity {
+			vel: Vector3::zeros(),
+			dir_vel: 0.,
+		},
+		components::Drawable {
+			size: size,
+			sprite_sheet: "data/green_explosion.cfg".into(),
+		},
+		components::Solid {
+			mass: 0.,
+			size: size / 2.,
+			collision_class: components::CollisionClass::Gas,
+		},
+		components::GasCloud {
+			base_size: size,
+			growth_rate: 16.,
+		},
+		components::CreationTime { time: state.time() },
+		components::TimeToDie {
+			time_to_die: state.time() + 3.,
+		},
+		components::OnContactEffect {
+			effects: vec![components::ContactEffect::DamageOverTime {
+				damage_rate: components::Damage {
+					amount: 2.,
+					damage_type: components::DamageType::Poison,
+					effect: Some(components::HitEffect {
+						effect_type: components::StatusEffectType::Poisoned,
+						strength: 4.,
+						duration: 4.,
+					}),
 				},
 			}],
 		},
@@ -1129,40 +1589,14 @@ pub fn spawn_explosion(
 }
 
 pub fn spawn_player(
-	pos: Point3<f32>, dir: f32, player_class: game_state::PlayerClass, health: components::Health,
-	weapon_set: components::WeaponSet, world: &mut hecs::World,
+	pos: Point3<f32>, dir: f32, player_class: &game_state::PlayerClass, health: components::Health,
+	weapon_set: components::WeaponSet, ability: Option<components::Ability>,
+	world: &mut hecs::World,
 ) -> hecs::Entity
 {
-	let (sprite_sheet, corpse_sprite_sheet, regen, speed, can_strafe, sound) = match player_class
-	{
-		game_state::PlayerClass::Santa => (
-			"data/santa.cfg",
-			"data/santa_corpse.cfg",
-			components::AmmoRegen {
-				weapon_type: components::WeaponType::SantaGun,
-				ammount: 5,
-				time_to_regen: 0.,
-			},
-			100.,
-			true,
-			"data/santa_death.ogg",
-		),
-		game_state::PlayerClass::Reindeer => (
-			"data/reindeer.cfg",
-			"data/reindeer_corpse.cfg",
-			components::AmmoRegen {
-				weapon_type: components::WeaponType::SantaGun,
-				ammount: 2,
-				time_to_regen: 0.,
-			},
-			150.,
-			false,
-			"data/reindeer_death.ogg",
-		),
-	};
-
+	let corpse_sprite_sheet = player_class.corpse_sprite_sheet.clone();
 	let size = TILE / 8.;
-	world.spawn((
+	let entity = world.spawn((
 		components::Position { pos: pos, dir: dir },
 		components::Velocity {
 			vel: Vector3::zeros(),
@@ -1170,7 +1604,7 @@ pub fn spawn_player(
 		},
 		components::Drawable {
 			size: size,
-			sprite_sheet: sprite_sheet.into(),
+			sprite_sheet: player_class.sprite_sheet.clone(),
 		},
 		components::Solid {
 			size: size / 2.,
@@ -1179,7 +1613,7 @@ pub fn spawn_player(
 		},
 		weapon_set,
 		health,
-		components::Freezable { amount: 0. },
+		components::StatusEffects::new(),
 		components::OnDeathEffect {
 			effects: vec![
 				components::DeathEffect::Spawn(Box::new(move |pos, dir, vel, _, world| {
@@ -1188,82 +1622,152 @@ pub fn spawn_player(
 						dir,
 						vel,
 						size,
-						corpse_sprite_sheet.into(),
+						corpse_sprite_sheet.clone(),
 						components::Team::Player,
 						world,
 					)
 				})),
 				components::DeathEffect::PlaySound {
-					sound: sound.into(),
+					sound: player_class.death_sound.clone(),
 					volume: 1.,
 				},
 			],
 		},
 		components::Team::Player,
-		regen,
+		components::AmmoRegen {
+			weapon_type: components::WeaponType::SantaGun,
+			ammount: player_class.ammo_regen,
+			time_to_regen: 0.,
+		},
 		components::Moveable {
-			speed: speed,
+			speed: player_class.speed,
 			rot_speed: f32::pi(),
-			can_strafe: can_strafe,
-		},
-	))
+			can_strafe: player_class.can_strafe,
+		},
+	));
+	// A saved ability keeps its energy and cooldown, otherwise start with a fresh one.
+	let ability = ability.or_else(|| {
+		player_class.ability.clone().map(|mut ability| {
+			ability.energy = ability.max_energy;
+			ability
+		})
+	});
+	if let Some(ability) = ability
+	{
+		world.insert_one(entity, ability).unwrap();
+	}
+	entity
 }
 
-pub fn spawn_buggy(
-	pos: Point3<f32>, dir: f32, counter_name: &str, world: &mut hecs::World,
+pub fn spawn_present_bomb(
+	pos: Point3<f32>, dir: f32, fuse: f64, damage: f32, radius: f32,
+	state: &mut game_state::GameState, world: &mut hecs::World,
 ) -> hecs::Entity
 {
-	let size = 4. * TILE / 8.;
-
-	let mut on_death_effects = vec![
-		components::DeathEffect::Spawn(Box::new(move |pos, _, _, state, world| {
-			spawn_explosion(pos, size, "data/smoke.cfg".into(), 0.25, state, world)
-		})),
-		components::DeathEffect::PlaySound {
-			sound: "data/explosion.ogg".into(),
-			volume: 2.,
-		},
-	];
-	if !counter_name.is_empty()
-	{
-		on_death_effects.push(components::DeathEffect::IncrementCounter {
-			target: counter_name.into(),
-		});
-	}
-
+	let size = 0.2 * TILE;
 	world.spawn((
 		components::Position { pos: pos, dir: dir },
 		components::Velocity {
-			vel: Vector3::zeros(),
+			vel: 96. * utils::dir_vec3(dir),
 			dir_vel: 0.,
 		},
+		components::AffectedByFriction,
 		components::Drawable {
 			size: size,
-			sprite_sheet: "data/buggy.cfg".into(),
+			sprite_sheet: "data/presents.cfg".into(),
 		},
 		components::Solid {
 			size: size / 2.,
-			mass: 5.,
-			collision_class: components::CollisionClass::Regular,
-		},
+			mass: 1.,
+			collision_class: components::CollisionClass::Tiny,
+		},
+		components::TimeToDie {
+			time_to_die: state.time() + fuse,
+		},
+		components::OnDeathEffect {
+			effects: vec![
+				components::DeathEffect::Spawn(Box::new(move |pos, _, _, state, world| {
+					spawn_explosion(pos, radius, "data/smoke.cfg".into(), 0.25, state, world)
+				})),
+				components::DeathEffect::DamageInRadius {
+					damage: components::Damage {
+						amount: damage,
+						damage_type: components::DamageType::Regular,
+						effect: None,
+					},
+					radius: radius,
+					push_strength: 200.,
+				},
+				components::DeathEffect::PlaySound {
+					sound: "data/explosion.ogg".into(),
+					volume: 2.,
+				},
+			],
+		},
+	))
+}
+
+pub fn spawn_vehicle(
+	pos: Point3<f32>, dir: f32, vehicle_type: &game_state::VehicleType, counter_name: &str,
+	world: &mut hecs::World,
+) -> hecs::Entity
+{
+	let size = vehicle_type.size;
+
+	let mut on_death_effects = vec![
+		components::DeathEffect::Spawn(Box::new(move |pos, _, _, state, world| {
+			spawn_explosion(pos, size, "data/smoke.cfg".into(), 0.25, state, world)
+		})),
+		components::DeathEffect::PlaySound {
+			sound: "data/explosion.ogg".into(),
+			volume: 2.,
+		},
+	];
+	if !counter_name.is_empty()
+	{
+		on_death_effects.push(components::DeathEffect::IncrementCounter {
+			target: counter_name.into(),
+		});
+	}
+
+	world.spawn((
+		components::Position { pos: pos, dir: dir },
+		components::Velocity {
+			vel: Vector3::zeros(),
+			dir_vel: 0.,
+		},
+		components::Drawable {
+			size: size,
+			sprite_sheet: vehicle_type.sprite_sheet.clone(),
+		},
+		components::Solid {
+			size: size / 2.,
+			mass: vehicle_type.mass,
+			collision_class: components::CollisionClass::Regular,
+		},
 		components::Health {
-			health: 200.,
-			armour: 100.,
-			max_health: 200.,
-			max_armour: 100.,
+			health: vehicle_type.health,
+			armour: vehicle_type.armour,
+			max_health: vehicle_type.health,
+			max_armour: vehicle_type.armour,
 			immunities: vec![],
 		},
-		components::Freezable { amount: 0. },
+		components::StatusEffects::new(),
 		components::OnDeathEffect {
 			effects: on_death_effects,
 		},
 		components::Team::Neutral,
 		components::WeaponSet {
-			weapons: HashMap::from([(
-				components::WeaponType::BuggyGun,
-				components::Weapon::buggy_gun(),
-			)]),
-			cur_weapon: components::WeaponType::BuggyGun,
+			weapons: vehicle_type
+				.weapons
+				.iter()
+				.map(|w| (w.weapon.weapon_type, w.weapon.clone()))
+				.collect(),
+			cur_weapon: vehicle_type
+				.weapons
+				.first()
+				.map(|w| w.weapon.weapon_type)
+				.unwrap_or(components::WeaponType::BuggyGun),
 			want_to_fire: false,
 			last_fire_time: -f64::INFINITY,
 		},
@@ -1271,13 +1775,22 @@ pub fn spawn_buggy(
 			contents: None,
 			saved_health: None,
 			saved_weapon_set: None,
+			entry_radius: vehicle_type.entry_radius,
+			exit_forward: vehicle_type.exit_forward,
+			exit_side: vehicle_type.exit_side,
+			weapons: vehicle_type
+				.weapons
+				.iter()
+				.map(|w| (w.weapon.weapon_type, w.ammo_name.clone()))
+				.collect(),
+			sound: vehicle_type.sound.clone(),
 		},
 		components::Moveable {
-			speed: 200.,
+			speed: vehicle_type.speed,
 			rot_speed: f32::pi(),
-			can_strafe: false,
+			can_strafe: vehicle_type.can_strafe,
 		},
-		components::AffectedByFriction,
+		vehicle_type.physics,
 	))
 }
 
@@ -1436,7 +1949,7 @@ pub fn spawn_cat(
 			max_armour: 5.,
 			immunities: vec![components::DamageType::Flame],
 		},
-		components::Freezable { amount: 0. },
+		components::StatusEffects::new(),
 		components::OnDeathEffect {
 			effects: on_death_effects,
 		},
@@ -1522,7 +2035,7 @@ pub fn spawn_big_cat(
 			max_armour: 5.,
 			immunities: vec![components::DamageType::Flame],
 		},
-		components::Freezable { amount: 0. },
+		components::StatusEffects::new(),
 		components::OnDeathEffect {
 			effects: on_death_effects,
 		},
@@ -1574,6 +2087,9 @@ pub fn spawn_grinch(
 				world,
 			)
 		})),
+		components::DeathEffect::Spawn(Box::new(move |pos, _, _, state, world| {
+			spawn_poison_cloud(pos, state, world)
+		})),
 		components::DeathEffect::PlaySound {
 			sound: "data/grinch_death.ogg".into(),
 			volume: 1.,
@@ -1608,7 +2124,7 @@ pub fn spawn_grinch(
 			max_armour: 0.,
 			immunities: vec![],
 		},
-		components::Freezable { amount: 0. },
+		components::StatusEffects::new(),
 		components::OnDeathEffect {
 			effects: on_death_effects,
 		},
@@ -1686,7 +2202,7 @@ pub fn spawn_snowman(
 			max_armour: 5.,
 			immunities: vec![components::DamageType::Cold(0.)],
 		},
-		components::Freezable { amount: 0. },
+		components::StatusEffects::new(),
 		components::OnDeathEffect {
 			effects: on_death_effects,
 		},
@@ -1783,7 +2299,7 @@ pub fn spawn_krampus(
 			max_armour: 5.,
 			immunities: vec![components::DamageType::Flame],
 		},
-		components::Freezable { amount: 0. },
+		components::StatusEffects::new(),
 		components::OnDeathEffect {
 			effects: on_death_effects,
 		},
@@ -1861,7 +2377,7 @@ pub fn spawn_big_snowman(
 			max_armour: 5.,
 			immunities: vec![components::DamageType::Cold(0.)],
 		},
-		components::Freezable { amount: 0. },
+		components::StatusEffects::new(),
 		components::OnDeathEffect {
 			effects: on_death_effects,
 		},
@@ -1925,16 +2441,21 @@ pub fn spawn_spawner(
 }
 
 pub fn spawn_area_trigger(
-	start: Point2<f32>, end: Point2<f32>, targets: Vec<String>, active: bool,
-	world: &mut hecs::World,
+	shape: components::TriggerShape, filter: components::TriggerFilter,
+	targets: Vec<components::Target>, exit_targets: Vec<components::Target>,
+	rearm_delay: Option<f64>, active: bool, world: &mut hecs::World,
 ) -> hecs::Entity
 {
 	world.spawn((
 		components::Active { active: active },
 		components::AreaTrigger {
-			start: start,
-			end: end,
+			shape: shape,
+			filter: filter,
 			targets: targets,
+			exit_targets: exit_targets,
+			rearm_delay: rearm_delay,
+			time_to_rearm: 0.,
+			occupied: false,
 		},
 	))
 }
@@ -1953,8 +2474,24 @@ pub fn spawn_sound(
 	))
 }
 
+pub fn spawn_ambient_sound(
+	pos: Point3<f32>, sound: &str, volume: f32, radius: f32, active: bool, world: &mut hecs::World,
+) -> hecs::Entity
+{
+	world.spawn((
+		components::Position { pos: pos, dir: 0. },
+		components::Active { active: active },
+		components::AmbientSound {
+			sound: sound.into(),
+			volume: volume,
+			radius: radius,
+		},
+	))
+}
+
 pub fn spawn_counter(
-	max_count: i32, targets: Vec<String>, active: bool, world: &mut hecs::World,
+	max_count: i32, targets: Vec<components::Target>, repeat: bool, active: bool,
+	world: &mut hecs::World,
 ) -> hecs::Entity
 {
 	world.spawn((
@@ -1963,15 +2500,32 @@ pub fn spawn_counter(
 			count: 0,
 			max_count: max_count,
 			targets: targets,
+			repeat: repeat,
+		},
+	))
+}
+
+pub fn spawn_message(
+	message: String, speaker: String, color: Color, duration: f64, active: bool,
+	world: &mut hecs::World,
+) -> hecs::Entity
+{
+	world.spawn((
+		components::Active { active: active },
+		components::Message {
+			message: message,
+			speaker: speaker,
+			color: color,
+			duration: duration,
 		},
 	))
 }
 
-pub fn spawn_message(message: String, active: bool, world: &mut hecs::World) -> hecs::Entity
+pub fn spawn_music(playlist: Vec<String>, active: bool, world: &mut hecs::World) -> hecs::Entity
 {
 	world.spawn((
 		components::Active { active: active },
-		components::Message { message: message },
+		components::Music { playlist: playlist },
 	))
 }
 
@@ -1986,8 +2540,8 @@ pub fn spawn_next_level(next_level: String, active: bool, world: &mut hecs::Worl
 }
 
 pub fn spawn_trigger(
-	delay: f64, targets: Vec<String>, active: bool, state: &mut game_state::GameState,
-	world: &mut hecs::World,
+	delay: f64, repeat: i32, targets: Vec<components::Target>, active: bool,
+	state: &mut game_state::GameState, world: &mut hecs::World,
 ) -> hecs::Entity
 {
 	world.spawn((
@@ -1996,10 +2550,73 @@ pub fn spawn_trigger(
 			delay: delay,
 			time_to_trigger: state.time() + delay,
 			targets: targets,
+			repeat: repeat,
+		},
+	))
+}
+
+pub fn spawn_relay(
+	gate: components::Gate, inputs: Vec<String>, targets: Vec<components::Target>, once: bool,
+	active: bool, world: &mut hecs::World,
+) -> hecs::Entity
+{
+	world.spawn((
+		components::Active { active: active },
+		components::Relay {
+			gate: gate,
+			inputs: inputs,
+			targets: targets,
+			output: false,
+			once: once,
 		},
 	))
 }
 
+pub fn spawn_objective(
+	text: String, counter: String, optional: bool, targets: Vec<components::Target>, active: bool,
+	world: &mut hecs::World,
+) -> hecs::Entity
+{
+	world.spawn((
+		components::Active { active: active },
+		components::Objective {
+			text: text,
+			counter: counter,
+			optional: optional,
+			progress: None,
+			complete: false,
+			targets: targets,
+		},
+	))
+}
+
+pub fn spawn_cutscene(
+	path: Vec<Point3<f32>>, look_at: String, duration: f64, pages: Vec<components::DialoguePage>,
+	targets: Vec<components::Target>, active: bool, world: &mut hecs::World,
+) -> hecs::Entity
+{
+	world.spawn((
+		components::Active { active: active },
+		components::Cutscene {
+			path: path,
+			look_at: look_at,
+			duration: duration,
+			pages: pages,
+			targets: targets,
+		},
+	))
+}
+
+pub fn spawn_camera_point(pos: Point3<f32>, world: &mut hecs::World) -> hecs::Entity
+{
+	world.spawn((components::Position { pos: pos, dir: 0. },))
+}
+
+pub fn spawn_flag(active: bool, world: &mut hecs::World) -> hecs::Entity
+{
+	world.spawn((components::Active { active: active },))
+}
+
 pub fn spawn_deleter(targets: Vec<String>, active: bool, world: &mut hecs::World) -> hecs::Entity
 {
 	world.spawn((
@@ -2019,8 +2636,60 @@ pub fn spawn_player_start(
 	))
 }
 
+fn inflict_status_effect(
+	effect: components::StatusEffect, health: &components::Health, id: hecs::Entity,
+	world: &hecs::World,
+)
+{
+	if health.is_immune_to_effect(effect.effect_type)
+	{
+		return;
+	}
+	if let Ok(mut status_effects) = world.get::<&mut components::StatusEffects>(id)
+	{
+		status_effects.add(effect);
+	}
+}
+
+// The lasting effects of damage that was just dealt.
+fn inflict_damage_effects(
+	damage: components::Damage, factor: f32, health: &components::Health, id: hecs::Entity,
+	time: f64, world: &hecs::World,
+)
+{
+	if let components::DamageType::Cold(amount) = damage.damage_type
+	{
+		if !health.is_immune_to_effect(components::StatusEffectType::Frozen)
+		{
+			if let Ok(mut status_effects) = world.get::<&mut components::StatusEffects>(id)
+			{
+				status_effects.add_chill(amount * factor, time);
+			}
+		}
+	}
+	if let Some(effect) = damage.effect
+	{
+		// Effects that intensify build up with the damage dealt.
+		let strength = match effect.effect_type.stacking()
+		{
+			components::Stacking::Intensify(_) => effect.strength * factor,
+			_ => effect.strength,
+		};
+		inflict_status_effect(
+			components::StatusEffect {
+				effect_type: effect.effect_type,
+				strength: strength,
+				until: time + effect.duration,
+			},
+			health,
+			id,
+			world,
+		);
+	}
+}
+
 fn str_to_spawn_fn(
-	name: &str,
+	name: &str, state: &game_state::GameState,
 ) -> Result<
 	Arc<
 		dyn Fn(Point3<f32>, f32, &str, &mut game_state::GameState, &mut hecs::World) -> hecs::Entity
@@ -2049,7 +2718,6 @@ fn str_to_spawn_fn(
 			Arc::new(|pos, dir, counter, _, world| spawn_krampus(pos, dir, counter, world))
 		}
 		"grinch" => Arc::new(|pos, dir, counter, _, world| spawn_grinch(pos, dir, counter, world)),
-		"buggy" => Arc::new(|pos, dir, counter, _, world| spawn_buggy(pos, dir, counter, world)),
 		"suit" => Arc::new(|pos, _, counter, _, world| {
 			spawn_item(pos, components::ItemType::Suit, counter, world)
 		}),
@@ -2099,7 +2767,16 @@ fn str_to_spawn_fn(
 				world,
 			)
 		}),
-		other => return Err(format!("Unknown spawn type '{}'", other).into()),
+		other =>
+		{
+			let vehicle_type = state
+				.vehicle_type(other)
+				.ok_or_else(|| format!("Unknown spawn type '{}'", other))?
+				.clone();
+			Arc::new(move |pos, dir, counter, _, world| {
+				spawn_vehicle(pos, dir, &vehicle_type, counter, world)
+			})
+		}
 	})
 }
 
@@ -2248,6 +2925,18 @@ fn turn_towards(
 	}
 }
 
+struct CutsceneState
+{
+	entity: hecs::Entity,
+	start_time: f64,
+	page: usize,
+	page_start_time: f64,
+	eye: Point3<f32>,
+	target: Point3<f32>,
+	next_page: bool,
+	skip: bool,
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 enum UIState
 {
@@ -2259,7 +2948,6 @@ enum UIState
 
 pub struct Map
 {
-	projection: Perspective3<f32>,
 	display_width: f32,
 	display_height: f32,
 
@@ -2268,6 +2956,8 @@ pub struct Map
 	player: hecs::Entity,
 	lifes: i32,
 	camera_anchor: components::Position,
+	camera_shake: f32,
+	last_player_health: Option<(hecs::Entity, f32)>,
 
 	want_spawn: bool,
 
@@ -2275,40 +2965,68 @@ pub struct Map
 	saved_weapon_set: components::WeaponSet,
 
 	test: hecs::Entity,
-	named_entities: HashMap<String, hecs::Entity>,
+	named_entities: NamedEntities,
 	active_player_start: hecs::Entity,
 
 	player_class: game_state::PlayerClass,
+	campaign_state: game_state::CampaignState,
 
 	ui_state: UIState,
-	message: Vec<String>,
-	time_to_hide_message: f64,
+	ui_state_before_menu: UIState,
+	messages: messages::MessageQueue,
 
 	subscreens: Vec<ui::SubScreen>,
 
+	script: Option<script::Script>,
+	script_events: Vec<script::Event>,
+
+	cutscene: Option<CutsceneState>,
+
 	world: hecs::World,
 }
 
 impl Map
 {
 	pub fn new(
-		state: &mut game_state::GameState, level: &str, player_class: game_state::PlayerClass,
-		saved_health: Option<components::Health>, saved_weapon_set: Option<components::WeaponSet>,
-		lives: i32, display_width: f32, display_height: f32,
+		state: &mut game_state::GameState, campaign_state: game_state::CampaignState,
+		display_width: f32, display_height: f32,
 	) -> Result<Self>
 	{
+		let player_class = state.player_class(&campaign_state.player_class)?.clone();
+		let saved_health = campaign_state.health.clone();
+		let saved_weapon_set = campaign_state.weapon_set.clone();
+		let lives = campaign_state.lives;
 		let mut world = hecs::World::default();
-		let mut named_entities = HashMap::new();
+		let mut named_entities = NamedEntities::new();
 		state.hide_mouse = true;
 		state.paused = false;
 
-		let level_desc: LevelDesc = utils::load_config(level)?;
+		let level_desc: LevelDesc = utils::load_config(&campaign_state.level)?;
 
 		if state.options.play_music
 		{
-			state.sfx.set_music_file(&level_desc.music);
+			state.sfx.set_playlist(level_desc.playlist());
 			state.sfx.play_music()?;
+			state.sfx.set_combat_music(
+				if level_desc.combat_music.is_empty()
+				{
+					None
+				}
+				else
+				{
+					Some(&level_desc.combat_music)
+				},
+			)?;
+		}
+
+		let script = if level_desc.script.is_empty()
+		{
+			None
 		}
+		else
+		{
+			Some(script::Script::new(&level_desc.script)?)
+		};
 
 		let level = Level::new(level_desc, &mut named_entities, state, &mut world)?;
 
@@ -2348,16 +3066,18 @@ impl Map
 		state.cache_sprite_sheet("data/flame_cloud.cfg")?;
 		state.cache_sprite_sheet("data/purple_explosion.cfg")?;
 		state.cache_sprite_sheet("data/green_explosion.cfg")?;
-		state.cache_sprite_sheet("data/buggy.cfg")?;
+		for vehicle_type in state.vehicle_types.vehicles.clone()
+		{
+			state.cache_sprite_sheet(&vehicle_type.sprite_sheet)?;
+			state.sfx.cache_sample(&vehicle_type.sound)?;
+		}
 		state.cache_sprite_sheet("data/snowman.cfg")?;
 		state.cache_sprite_sheet("data/cat.cfg")?;
 		state.cache_sprite_sheet("data/cat_corpse.cfg")?;
 		state.cache_sprite_sheet("data/grinch.cfg")?;
 		state.cache_sprite_sheet("data/grinch_corpse.cfg")?;
-		state.cache_sprite_sheet("data/santa.cfg")?;
-		state.cache_sprite_sheet("data/santa_corpse.cfg")?;
-		state.cache_sprite_sheet("data/reindeer.cfg")?;
-		state.cache_sprite_sheet("data/reindeer_corpse.cfg")?;
+		state.cache_sprite_sheet(&player_class.sprite_sheet)?;
+		state.cache_sprite_sheet(&player_class.corpse_sprite_sheet)?;
 		state.cache_sprite_sheet("data/bullet.cfg")?;
 		state.cache_sprite_sheet("data/armor_shard.cfg")?;
 		state.cache_sprite_sheet("data/armor_suit.cfg")?;
@@ -2380,9 +3100,7 @@ impl Map
 
 		state.sfx.cache_sample("data/big_cat_death.ogg")?;
 		state.sfx.cache_sample("data/big_cat.ogg")?;
-		state.sfx.cache_sample("data/santa_death.ogg")?;
-		state.sfx.cache_sample("data/reindeer_death.ogg")?;
-		state.sfx.cache_sample("data/buggy.ogg")?;
+		state.sfx.cache_sample(&player_class.death_sound)?;
 		state.sfx.cache_sample("data/bullet_fire.ogg")?;
 		state.sfx.cache_sample("data/bullet_hit.ogg")?;
 		state.sfx.cache_sample("data/cat_death.ogg")?;
@@ -2411,21 +3129,27 @@ impl Map
 		state.sfx.cache_sample("data/heart.ogg")?;
 		state.sfx.cache_sample("data/suit.ogg")?;
 
+		state.controls.set_frozen(false);
+
 		Ok(Self {
+			cutscene: None,
+			script: script,
+			script_events: vec![script::Event::LevelStart],
 			test: test,
-			projection: utils::projection_transform(display_width, display_height),
 			display_width: display_width,
 			display_height: display_height,
 			level: level,
 			player: player_start,
 			camera_anchor: camera_anchor,
+			camera_shake: 0.,
+			last_player_health: None,
 			world: world,
 			want_spawn: true,
 			active_player_start: player_start_entity.unwrap(),
 			saved_health: saved_health.unwrap_or(components::Health {
-				health: 100.,
+				health: player_class.health,
 				armour: 0.,
-				max_health: 100.,
+				max_health: player_class.health,
 				max_armour: 100.,
 				immunities: vec![],
 			}),
@@ -2437,11 +3161,7 @@ impl Map
 					),
 					(
 						components::WeaponType::SantaGun,
-						match player_class
-						{
-							game_state::PlayerClass::Santa => components::Weapon::santa_gun(),
-							game_state::PlayerClass::Reindeer => components::Weapon::rocket_gun(),
-						},
+						player_class.weapon.clone(),
 					),
 					(
 						components::WeaponType::RocketGun,
@@ -2462,40 +3182,178 @@ impl Map
 			}),
 			named_entities: named_entities,
 			lifes: lives,
-			message: vec![],
-			time_to_hide_message: 0.,
+			messages: messages::MessageQueue::new(),
 			player_class: player_class,
+			campaign_state: campaign_state,
 			ui_state: UIState::Regular,
+			ui_state_before_menu: UIState::Regular,
 			subscreens: vec![],
 		})
 	}
 
-	fn camera_pos(&self) -> Point3<f32>
+	fn camera_pos(&self, camera_mode: game_state::CameraMode) -> Point3<f32>
 	{
-		let rot = Rotation2::new(self.camera_anchor.dir);
-		let offt = rot * Vector2::new(0., -TILE / 2.);
-		let height = TILE / 2.2;
-
-		Point3::new(
-			self.camera_anchor.pos.x + offt.x,
-			height,
-			self.camera_anchor.pos.z + offt.y,
-		)
+		match camera_mode
+		{
+			game_state::CameraMode::ThirdPerson =>
+			{
+				let rot = Rotation2::new(self.camera_anchor.dir);
+				let offt = rot * Vector2::new(0., -TILE / 2.);
+				let height = TILE / 2.2;
+
+				Point3::new(
+					self.camera_anchor.pos.x + offt.x,
+					height,
+					self.camera_anchor.pos.z + offt.y,
+				)
+			}
+			game_state::CameraMode::FirstPerson => Point3::new(
+				self.camera_anchor.pos.x,
+				TILE / 6.,
+				self.camera_anchor.pos.z,
+			),
+		}
+	}
+
+	// Sounds the player makes aren't muffled, since the third person camera can end up
+	// behind a wall.
+	fn is_sound_occluded(
+		&self, source: hecs::Entity, sound_pos: Point2<f32>, sound_camera: Point2<f32>,
+	) -> bool
+	{
+		source != self.player && self.level.check_segment(sound_pos, sound_camera, 0.)
 	}
 
-	fn make_camera(&self) -> Isometry3<f32>
+	fn play_sound_at(
+		&self, state: &mut game_state::GameState, name: &str, source: hecs::Entity,
+		sound_pos: Point2<f32>, volume: f32,
+	) -> Result<()>
 	{
-		let camera_pos = self.camera_pos();
-
-		utils::camera_project(
-			camera_pos.x,
-			camera_pos.y,
-			camera_pos.z,
-			self.camera_anchor.pos.x,
-			self.camera_anchor.pos.z,
+		let sound_camera = self.camera_pos(state.options.camera_mode).xz();
+		state.sfx.play_positional_sound(
+			name,
+			sfx::SoundPlacement {
+				sound_pos: sound_pos,
+				camera_pos: sound_camera,
+				camera_dir: self.camera_anchor.dir,
+				occluded: self.is_sound_occluded(source, sound_pos, sound_camera),
+			},
+			volume,
 		)
 	}
 
+	fn make_camera(&self, state: &game_state::GameState) -> Isometry3<f32>
+	{
+		if let Some(cutscene) = &self.cutscene
+		{
+			return utils::camera_project(cutscene.eye, cutscene.target);
+		}
+		let camera_mode = state.options.camera_mode;
+		let mut eye = self.camera_pos(camera_mode);
+		let mut target = match camera_mode
+		{
+			game_state::CameraMode::ThirdPerson =>
+			{
+				Point3::new(self.camera_anchor.pos.x, eye.y, self.camera_anchor.pos.z)
+			}
+			game_state::CameraMode::FirstPerson => eye + utils::dir_vec3(self.camera_anchor.dir),
+		};
+
+		let shake = state.options.screen_shake * self.camera_shake * self.camera_shake;
+		if shake > 0.
+		{
+			let t = state.time() as f32;
+			eye +=
+				3. * shake * Vector3::new((37. * t).sin(), 0.5 * (41. * t).sin(), (43. * t).cos());
+			target += 3. * shake * Vector3::new((29. * t).cos(), (31. * t).sin(), (47. * t).sin());
+		}
+
+		utils::camera_project(eye, target)
+	}
+
+	fn add_camera_shake(&mut self, amount: f32)
+	{
+		self.camera_shake = utils::min(1., self.camera_shake + amount);
+	}
+
+	fn objective_lines(&self) -> Vec<(String, Color)>
+	{
+		let mut objectives: Vec<_> = self
+			.world
+			.query::<(&components::Objective, &components::Active)>()
+			.iter()
+			.filter(|(_, (_, active))| active.active)
+			.map(|(id, (objective, _))| {
+				let mut text = objective.text.clone();
+				if let Some((count, max_count)) = objective.progress
+				{
+					text = format!("{}/{} {}", count, max_count, text);
+				}
+				if objective.optional
+				{
+					text = format!("{} (OPTIONAL)", text);
+				}
+				let (text, color) = if objective.complete
+				{
+					(format!("[X] {}", text), Color::from_rgb_f(0.5, 0.5, 0.5))
+				}
+				else
+				{
+					(format!("[ ] {}", text), Color::from_rgb_f(1., 1., 0.8))
+				};
+				(id.to_bits().get(), text, color)
+			})
+			.collect();
+		// Entities are created in TMX order.
+		objectives.sort_by_key(|(id, _, _)| *id);
+		objectives
+			.into_iter()
+			.map(|(_, text, color)| (text, color))
+			.collect()
+	}
+
+	// `exit` is either the name of a campaign exit, a level filename or empty for the default exit.
+	fn next_level_screen(&self, state: &game_state::GameState, exit: &str)
+		-> game_state::NextScreen
+	{
+		let next_level = match state.campaign.next_level(&self.campaign_state.level, exit)
+		{
+			Some(next_level) => next_level,
+			None =>
+			{
+				return match state.campaign.find_episode(&self.campaign_state.level)
+				{
+					Some(episode) if !episode.ending.is_empty() =>
+					{
+						game_state::NextScreen::Slideshow(
+							episode.ending.clone(),
+							Box::new(game_state::NextScreen::Credits),
+						)
+					}
+					_ => game_state::NextScreen::Menu,
+				};
+			}
+		};
+		let mut saved_health = self.saved_health.clone();
+		let mut saved_weapon_set = self.saved_weapon_set.clone();
+		if let Ok(health) = self.world.get::<&components::Health>(self.player)
+		{
+			saved_health = (*health).clone();
+		}
+		if let Ok(weapon_set) = self.world.get::<&components::WeaponSet>(self.player)
+		{
+			saved_weapon_set = (*weapon_set).clone();
+		}
+
+		game_state::NextScreen::Game(self.campaign_state.advance(
+			&state.campaign,
+			&next_level,
+			saved_health,
+			saved_weapon_set,
+			self.lifes,
+		))
+	}
+
 	pub fn logic(
 		&mut self, state: &mut game_state::GameState,
 	) -> Result<Option<game_state::NextScreen>>
@@ -2504,7 +3362,7 @@ impl Map
 		{
 			return Ok(None);
 		}
-		let sound_camera = self.camera_pos().xz();
+		let sound_camera = self.camera_pos(state.options.camera_mode).xz();
 		let mut to_die = vec![];
 
 		// Collision detection.
@@ -2546,6 +3404,7 @@ impl Map
 		}
 
 		let mut on_contact_effects = vec![];
+		let mut rams = vec![];
 		for pass in 0..5
 		{
 			for &(inner1, inner2) in &colliding_pairs
@@ -2585,6 +3444,13 @@ impl Map
 
 				if pass == 0
 				{
+					for (id, other_id) in [(id1, id2), (id2, id1)]
+					{
+						if self.world.get::<&components::VehiclePhysics>(id).is_ok()
+						{
+							rams.push((id, other_id));
+						}
+					}
 					for (id, other_id) in [(id1, Some(id2)), (id2, Some(id1))]
 					{
 						if let Ok(on_contact_effect) =
@@ -2624,6 +3490,65 @@ impl Map
 			}
 		}
 
+		// Ramming.
+		for (id, other_id) in rams
+		{
+			let physics = *self.world.get::<&components::VehiclePhysics>(id)?;
+			if self
+				.world
+				.get::<&components::Team>(other_id)
+				.map(|t| *t != components::Team::Monster)
+				.unwrap_or(true)
+			{
+				continue;
+			}
+			let diff = (self.world.get::<&components::Position>(other_id)?.pos
+				- self.world.get::<&components::Position>(id)?.pos)
+				.xz();
+			let normal = diff / utils::max(0.1, diff.norm());
+			let normal = Vector3::new(normal.x, 0., normal.y);
+			let vel = self.world.get::<&components::Velocity>(id)?.vel;
+			let other_vel = self
+				.world
+				.get::<&components::Velocity>(other_id)
+				.map(|v| v.vel)
+				.unwrap_or(Vector3::zeros());
+			let impact = (vel - other_vel).dot(&normal);
+			if impact < physics.min_ram_speed
+			{
+				continue;
+			}
+			if let Ok(mut health) = self.world.get::<&mut components::Health>(other_id)
+			{
+				let damage = components::Damage {
+					amount: physics.ram_damage * impact,
+					damage_type: components::DamageType::Regular,
+					effect: Some(components::HitEffect {
+						effect_type: components::StatusEffectType::Stunned,
+						strength: 1.,
+						duration: 0.5,
+					}),
+				};
+				if health.damage(damage, 1.)
+				{
+					inflict_damage_effects(
+						damage,
+						1.,
+						&health,
+						other_id,
+						state.time(),
+						&self.world,
+					);
+				}
+			}
+			// Spend the speed on the impact so it only counts once.
+			self.world.get::<&mut components::Velocity>(id)?.vel -= 0.5 * impact * normal;
+			if id == self.player
+			{
+				self.add_camera_shake(impact / 400.);
+			}
+		}
+
 		// On contact effects.
 		for (id, other_id, effects) in on_contact_effects
 		{
@@ -2634,40 +3559,35 @@ impl Map
 					(components::ContactEffect::Die, _) => to_die.push((true, id)),
 					(components::ContactEffect::Hurt { damage }, Some(other_id)) =>
 					{
-						let mut damaged = false;
 						if let Ok(mut health) = self.world.get::<&mut components::Health>(other_id)
 						{
-							damaged = health.damage(damage, 1.);
-						}
-						if damaged
-						{
-							if let components::DamageType::Cold(amount) = damage.damage_type
+							if health.damage(damage, 1.)
 							{
-								if let Ok(mut freezable) =
-									self.world.get::<&mut components::Freezable>(other_id)
-								{
-									freezable.amount = utils::min(2., freezable.amount + amount);
-								}
+								inflict_damage_effects(
+									damage,
+									1.,
+									&health,
+									other_id,
+									state.time(),
+									&self.world,
+								);
 							}
 						}
 					}
 					(components::ContactEffect::DamageOverTime { damage_rate }, Some(other_id)) =>
 					{
-						let mut damaged = false;
 						if let Ok(mut health) = self.world.get::<&mut components::Health>(other_id)
 						{
-							damaged = health.damage(damage_rate, utils::DT);
-						}
-						if damaged
-						{
-							if let components::DamageType::Cold(amount) = damage_rate.damage_type
+							if health.damage(damage_rate, utils::DT)
 							{
-								if let Ok(mut freezable) =
-									self.world.get::<&mut components::Freezable>(other_id)
-								{
-									freezable.amount =
-										utils::min(2., freezable.amount + amount * utils::DT);
-								}
+								inflict_damage_effects(
+									damage_rate,
+									utils::DT,
+									&health,
+									other_id,
+									state.time(),
+									&self.world,
+								);
 							}
 						}
 					}
@@ -2711,19 +3631,7 @@ impl Map
 									.map(|w| {
 										w.weapons
 											.get_mut(&components::WeaponType::SantaGun)
-											.map(|w| {
-												w.add_ammo(
-													if self.player_class
-														== game_state::PlayerClass::Santa
-													{
-														20
-													}
-													else
-													{
-														2
-													},
-												)
-											})
+											.map(|w| w.add_ammo(self.player_class.ammo_pickup))
 											.unwrap_or(false)
 									})
 									.unwrap_or(false),
@@ -2788,6 +3696,9 @@ impl Map
 							}
 							if picked_up
 							{
+								self.script_events.push(script::Event::ItemPickedUp(
+									item_type.name().to_string(),
+								));
 								to_die.push((true, id));
 							}
 						}
@@ -2797,6 +3708,29 @@ impl Map
 			}
 		}
 
+		// Vehicles do their own friction, so ones the player isn't driving need to coast.
+		for (id, (pos, vel, physics, moveable, status_effects)) in self.world.query_mut::<(
+			&components::Position,
+			&mut components::Velocity,
+			&components::VehiclePhysics,
+			&components::Moveable,
+			Option<&components::StatusEffects>,
+		)>()
+		{
+			if id == self.player
+				&& !status_effects
+					.map(|s| s.is_incapacitated())
+					.unwrap_or(false)
+			{
+				continue;
+			}
+			let input = components::DriveInput {
+				on_ice: self.level.is_ice(pos.pos),
+				..Default::default()
+			};
+			physics.drive(vel, pos.dir, input, moveable.speed, utils::DT);
+		}
+
 		// Friction.
 		for (_, (vel, _)) in self
 			.world
@@ -2830,8 +3764,8 @@ impl Map
 				.unwrap_or(false)
 			&& self
 				.world
-				.get::<&components::Freezable>(self.player)
-				.map(|f| !f.is_frozen())
+				.get::<&components::StatusEffects>(self.player)
+				.map(|s| !s.is_incapacitated())
 				.unwrap_or(true)
 		{
 			let get_action_state =
@@ -2854,21 +3788,119 @@ impl Map
 			let pos = *self.world.get::<&components::Position>(self.player)?;
 			let dir = pos.dir;
 			let rot = Rotation2::new(dir);
-			let speed = moveable.speed;
+			let speed = moveable.speed
+				* self
+					.world
+					.get::<&components::StatusEffects>(self.player)
+					.map(|s| s.speed_factor())
+					.unwrap_or(1.);
 			let vel = rot * Vector2::new(left_right * speed, up_down * speed);
 
+			let physics = self
+				.world
+				.get::<&components::VehiclePhysics>(self.player)
+				.ok()
+				.map(|p| *p);
 			{
 				let mut player_vel = self.world.get::<&mut components::Velocity>(self.player)?;
-				player_vel.vel = Vector3::new(vel.x, 0., vel.y);
-				player_vel.dir_vel = 3. * (rot_left_right * f32::pi() / 2.);
+				if let Some(physics) = physics
+				{
+					physics.drive(
+						&mut player_vel,
+						dir,
+						components::DriveInput {
+							throttle: up_down,
+							steer: rot_left_right,
+							on_ice: self.level.is_ice(pos.pos),
+						},
+						speed,
+						utils::DT,
+					);
+				}
+				else
+				{
+					player_vel.vel = Vector3::new(vel.x, 0., vel.y);
+					player_vel.dir_vel = 3. * (rot_left_right * f32::pi() / 2.);
+				}
+			}
+
+			let mut used_ability = None;
+			if let Ok(mut ability) = self.world.get::<&mut components::Ability>(self.player)
+			{
+				if ability.active
+				{
+					if let components::AbilityType::Charge { speed, .. } = ability.ability_type
+					{
+						self.world
+							.get::<&mut components::Velocity>(self.player)?
+							.vel = speed * utils::dir_vec3(dir);
+					}
+				}
+				else if get_action_state(state, controls::Action::UseAbility) > 0.5
+					&& ability.ready(state.time())
+				{
+					ability.energy -= ability.energy_cost;
+					ability.time_to_ready = state.time() + ability.cooldown;
+					if let components::AbilityType::Charge { duration, .. } = ability.ability_type
+					{
+						ability.active = true;
+						ability.active_until = state.time() + duration;
+					}
+					used_ability = Some(ability.ability_type);
+				}
+			}
+			match used_ability
+			{
+				Some(components::AbilityType::Charge { damage_rate, .. }) =>
+				{
+					// Removed by the Ability section once the charge is over.
+					if damage_rate > 0.
+					{
+						self.world.insert_one(
+							self.player,
+							components::OnContactEffect {
+								effects: vec![components::ContactEffect::DamageOverTime {
+									damage_rate: components::Damage {
+										amount: damage_rate,
+										damage_type: components::DamageType::Regular,
+										effect: None,
+									},
+								}],
+							},
+						)?;
+					}
+					state.sfx.play_sound("data/rocket_fire.ogg")?;
+				}
+				Some(components::AbilityType::PresentBomb {
+					fuse,
+					damage,
+					radius,
+				}) =>
+				{
+					spawn_present_bomb(
+						pos.pos + TILE / 8. * utils::dir_vec3(dir),
+						dir,
+						fuse,
+						damage,
+						radius,
+						state,
+						&mut self.world,
+					);
+					state.sfx.play_sound("data/snowball_fire.ogg")?;
+				}
+				None => (),
 			}
 
 			if get_action_state(state, controls::Action::EnterVehicle) > 0.5
 			{
 				let mut spawn_fn = None;
+				let mut exit_pos = pos.pos;
 				if let Ok(mut vehicle) = self.world.get::<&mut components::Vehicle>(self.player)
 				{
 					spawn_fn = vehicle.contents.take();
+					let forward = utils::dir_vec3(dir);
+					let side = Vector3::new(-forward.z, 0., forward.x);
+					exit_pos += vehicle.exit_forward * forward + vehicle.exit_side * side;
 					state
 						.controls
 						.clear_action_state(controls::Action::EnterVehicle);
@@ -2883,17 +3915,25 @@ impl Map
 				}
 				else
 				{
+					let max_radius = self
+						.world
+						.query::<&components::Vehicle>()
+						.iter()
+						.fold(0., |r, (_, vehicle)| utils::max(r, vehicle.entry_radius));
 					let entries = grid.query_rect(
-						Point2::new(pos.pos.x - 2. * TILE, pos.pos.z - 2. * TILE),
-						Point2::new(pos.pos.x + 2. * TILE, pos.pos.z + 2. * TILE),
+						Point2::new(pos.pos.x - max_radius, pos.pos.z - max_radius),
+						Point2::new(pos.pos.x + max_radius, pos.pos.z + max_radius),
 						|entry| {
-							if let (Ok(other_team), Ok(vehicle)) = (
+							if let (Ok(other_team), Ok(vehicle), Ok(other_pos)) = (
 								self.world.get::<&components::Team>(entry.inner.id),
 								self.world.get::<&components::Vehicle>(entry.inner.id),
+								self.world.get::<&components::Position>(entry.inner.id),
 							)
 							{
 								components::Team::Player.friendly(&other_team)
 									&& vehicle.contents.is_none()
+									&& (other_pos.pos.xz() - pos.pos.xz()).norm()
+										< vehicle.entry_radius
 							}
 							else
 							{
@@ -2901,15 +3941,19 @@ impl Map
 							}
 						},
 					);
-					if let Some(entry) = entries.get(0)
+					let closest = entries.iter().min_by(|a, b| {
+						let dist_a = (a.inner.pos.xz() - pos.pos.xz()).norm();
+						let dist_b = (b.inner.pos.xz() - pos.pos.xz()).norm();
+						dist_a.partial_cmp(&dist_b).unwrap()
+					});
+					if let Some(entry) = closest
 					{
-						state.sfx.play_positional_sound(
-							"data/buggy.ogg",
-							pos.pos.xz(),
-							sound_camera,
-							self.camera_anchor.dir,
-							1.,
-						)?;
+						let sound = self
+							.world
+							.get::<&components::Vehicle>(entry.inner.id)?
+							.sound
+							.clone();
+						self.play_sound_at(state, &sound, self.player, pos.pos.xz(), 1.)?;
 
 						let mut vehicle = self
 							.world
@@ -2920,16 +3964,24 @@ impl Map
 						let weapon_set =
 							(*self.world.get::<&components::WeaponSet>(self.player)?).clone();
 
-						let player_class = self.player_class;
+						// Keeps the ability's energy and cooldown for when the pilot gets out.
+						let ability = self
+							.world
+							.get::<&components::Ability>(self.player)
+							.map(|ability| (*ability).clone())
+							.ok();
+
+						let player_class = self.player_class.clone();
 						vehicle.saved_health = Some(health.clone());
 						vehicle.saved_weapon_set = Some(weapon_set.clone());
 						vehicle.contents = Some(Box::new(move |pos, dir, world| {
 							spawn_player(
 								pos,
 								dir,
-								player_class,
+								&player_class,
 								health.clone(),
 								weapon_set.clone(),
+								ability.clone(),
 								world,
 							)
 						}));
@@ -2948,59 +4000,55 @@ impl Map
 				}
 				if let Some(spawn_fn) = spawn_fn
 				{
-					self.player = spawn_fn(
-						pos.pos - 0.5 * TILE * utils::dir_vec3(dir),
-						dir,
-						&mut self.world,
-					);
+					self.player = spawn_fn(exit_pos, dir, &mut self.world);
 				}
 			}
 
+			let weapon_order = match self.world.get::<&components::Vehicle>(self.player)
+			{
+				Ok(vehicle) => vehicle.weapons.iter().map(|(w, _)| *w).collect(),
+				Err(_) => vec![
+					components::WeaponType::SantaGun,
+					components::WeaponType::FreezeGun,
+					components::WeaponType::OrbGun,
+				],
+			};
 			if let Ok(mut weapon_set) = self.world.get::<&mut components::WeaponSet>(self.player)
 			{
 				weapon_set.want_to_fire =
 					get_action_state(state, controls::Action::FireWeapon) > 0.5;
 
 				let mut desired_weapon = None;
-				if get_action_state(state, controls::Action::SelectWeapon1) > 0.5
-				{
-					desired_weapon = Some(components::WeaponType::SantaGun);
-				}
-				if get_action_state(state, controls::Action::SelectWeapon2) > 0.5
-				{
-					desired_weapon = Some(components::WeaponType::FreezeGun);
-				}
-				if get_action_state(state, controls::Action::SelectWeapon3) > 0.5
+				for (i, action) in [
+					controls::Action::SelectWeapon1,
+					controls::Action::SelectWeapon2,
+					controls::Action::SelectWeapon3,
+				]
+				.into_iter()
+				.enumerate()
 				{
-					desired_weapon = Some(components::WeaponType::OrbGun);
+					if get_action_state(state, action) > 0.5
+					{
+						desired_weapon = weapon_order.get(i).copied();
+					}
 				}
 
 				let change = get_action_state(state, controls::Action::NextWeapon)
 					- get_action_state(state, controls::Action::PrevWeapon);
-				if change != 0.
+				if change != 0. && !weapon_order.is_empty()
 				{
-					let mut cur_weapon_idx = match weapon_set.cur_weapon
-					{
-						components::WeaponType::SantaGun => 0,
-						components::WeaponType::FreezeGun => 1,
-						components::WeaponType::OrbGun => 2,
-						_ => 0,
-					};
-
-					loop
+					let num_weapons = weapon_order.len() as i32;
+					let mut cur_weapon_idx = weapon_order
+						.iter()
+						.position(|w| *w == weapon_set.cur_weapon)
+						.unwrap_or(0) as i32;
+
+					// Gives up after a full cycle, e.g. when there's only one weapon.
+					for _ in 0..num_weapons
 					{
-						cur_weapon_idx = (cur_weapon_idx + if change > 0. { 1 } else { -1 }) % 3;
-						while cur_weapon_idx < 0
-						{
-							cur_weapon_idx += 3;
-						}
-						let new_weapon = match cur_weapon_idx
-						{
-							0 => components::WeaponType::SantaGun,
-							1 => components::WeaponType::FreezeGun,
-							2 => components::WeaponType::OrbGun,
-							_ => unreachable!(),
-						};
+						cur_weapon_idx = (cur_weapon_idx + if change > 0. { 1 } else { -1 })
+							.rem_euclid(num_weapons);
+						let new_weapon = weapon_order[cur_weapon_idx as usize];
 						if weapon_set.weapons.contains_key(&new_weapon)
 						{
 							if weapon_set.weapons[&new_weapon].selectable
@@ -3027,7 +4075,8 @@ impl Map
 
 		// Weapon handling.
 		let mut proj_spawns = vec![];
-		for (_, (pos, weapon_set, solid)) in self.world.query_mut::<(
+		let mut fire_sounds = vec![];
+		for (id, (pos, weapon_set, solid)) in self.world.query_mut::<(
 			&components::Position,
 			&mut components::WeaponSet,
 			&components::Solid,
@@ -3090,6 +4139,13 @@ impl Map
 					proj_spawns.push((spawn_pos, pos.dir - f32::pi() / 3., weapon.weapon_type));
 					proj_spawns.push((spawn_pos, pos.dir + f32::pi() / 3., weapon.weapon_type));
 				}
+				components::WeaponType::ElfGun =>
+				{
+					let spawn_pos =
+						pos.pos + utils::dir_vec3(pos.dir) * (solid.size + proj_size + 1.);
+					proj_spawns.push((spawn_pos, pos.dir - f32::pi() / 24., weapon.weapon_type));
+					proj_spawns.push((spawn_pos, pos.dir + f32::pi() / 24., weapon.weapon_type));
+				}
 				components::WeaponType::KrampusGun =>
 				{
 					let forward = solid.size + proj_size + 1.;
@@ -3106,71 +4162,13 @@ impl Map
 				}
 			}
 
-			match weapon.weapon_type
-			{
-				components::WeaponType::RocketGun | components::WeaponType::KrampusGun =>
-				{
-					state.sfx.play_positional_sound(
-						"data/rocket_fire.ogg",
-						pos.pos.xz(),
-						sound_camera,
-						self.camera_anchor.dir,
-						1.,
-					)?;
-				}
-				components::WeaponType::FlameGun | components::WeaponType::BigFlameGun =>
-				{
-					state.sfx.play_positional_sound(
-						"data/fire.ogg",
-						pos.pos.xz(),
-						sound_camera,
-						self.camera_anchor.dir,
-						1.,
-					)?;
-				}
-				components::WeaponType::OrbGun =>
-				{
-					state.sfx.play_positional_sound(
-						"data/orb_fire.ogg",
-						pos.pos.xz(),
-						sound_camera,
-						self.camera_anchor.dir,
-						1.,
-					)?;
-				}
-				components::WeaponType::SantaGun | components::WeaponType::BuggyGun =>
-				{
-					state.sfx.play_positional_sound(
-						"data/bullet_fire.ogg",
-						pos.pos.xz(),
-						sound_camera,
-						self.camera_anchor.dir,
-						1.,
-					)?;
-				}
-				components::WeaponType::SnowmanGun | components::WeaponType::BigSnowmanGun =>
-				{
-					state.sfx.play_positional_sound(
-						"data/snowball_fire.ogg",
-						pos.pos.xz(),
-						sound_camera,
-						self.camera_anchor.dir,
-						1.,
-					)?;
-				}
-				components::WeaponType::FreezeGun =>
-				{
-					state.sfx.play_positional_sound(
-						"data/freeze.ogg",
-						pos.pos.xz(),
-						sound_camera,
-						self.camera_anchor.dir,
-						1.,
-					)?;
-				}
-			}
+			fire_sounds.push((id, pos.pos.xz(), weapon.weapon_type.fire_sound()));
 			weapon_set.last_fire_time = state.time();
 		}
+		for (id, pos, sound) in fire_sounds
+		{
+			self.play_sound_at(state, sound, id, pos, 1.)?;
+		}
 
 		for (pos, dir, weapon_type) in proj_spawns
 		{
@@ -3186,6 +4184,17 @@ impl Map
 						&mut self.world,
 					);
 				}
+				// Short ranged, to make up for the spread.
+				components::WeaponType::ElfGun =>
+				{
+					spawn_projectile(
+						pos + Vector3::new(0., 8., 0.),
+						dir,
+						0.75,
+						state,
+						&mut self.world,
+					);
+				}
 				components::WeaponType::RocketGun =>
 				{
 					spawn_rocket(pos + Vector3::new(0., 8., 0.), dir, state, &mut self.world);
@@ -3241,6 +4250,27 @@ impl Map
 			}
 		}
 
+		// Ability
+		let mut charge_ended = vec![];
+		for (id, ability) in self.world.query_mut::<&mut components::Ability>()
+		{
+			ability.energy = utils::min(
+				ability.max_energy,
+				ability.energy + ability.energy_regen * utils::DT,
+			);
+			if ability.active && state.time() >= ability.active_until
+			{
+				ability.active = false;
+				charge_ended.push(id);
+			}
+		}
+		for id in charge_ended
+		{
+			self.world
+				.remove_one::<components::OnContactEffect>(id)
+				.ok();
+		}
+
 		// Gas cloud.
 		for (_, (gas_cloud, creation_time, solid, drawable)) in self.world.query_mut::<(
 			&components::GasCloud,
@@ -3255,17 +4285,43 @@ impl Map
 			drawable.size = size / 2.;
 		}
 
-		// Freezable
-		for (id, freezable) in self.world.query::<&mut components::Freezable>().iter()
+		// Status effects
+		for (id, status_effects) in self.world.query::<&mut components::StatusEffects>().iter()
 		{
-			freezable.amount = utils::max(0., freezable.amount - 0.25 * utils::DT);
-			if freezable.is_frozen()
+			status_effects.update(state.time());
+			if status_effects.is_incapacitated()
 			{
 				if let Ok(mut weapon_set) = self.world.get::<&mut components::WeaponSet>(id)
 				{
 					weapon_set.want_to_fire = false;
 				}
 			}
+			if let Ok(mut health) = self.world.get::<&mut components::Health>(id)
+			{
+				for (effect_type, damage_type) in [
+					(
+						components::StatusEffectType::Burning,
+						components::DamageType::Flame,
+					),
+					(
+						components::StatusEffectType::Poisoned,
+						components::DamageType::Poison,
+					),
+				]
+				{
+					if let Some(effect) = status_effects.get(effect_type)
+					{
+						health.damage(
+							components::Damage {
+								amount: effect.strength,
+								damage_type: damage_type,
+								effect: None,
+							},
+							utils::DT,
+						);
+					}
+				}
+			}
 		}
 
 		// AI
@@ -3286,13 +4342,18 @@ impl Map
 			{
 				continue;
 			}
-			if let Ok(freezable) = self.world.get::<&components::Freezable>(id)
+			let speed_factor = match self.world.get::<&components::StatusEffects>(id)
 			{
-				if freezable.is_frozen()
+				Ok(status_effects) =>
 				{
-					continue;
+					if status_effects.is_incapacitated()
+					{
+						continue;
+					}
+					status_effects.speed_factor()
 				}
-			}
+				Err(_) => 1.,
+			};
 			//~ if id == self.test
 			//~ {
 			//~ println!("s---- {} {:?}", state.time(), ai.status);
@@ -3300,7 +4361,7 @@ impl Map
 			if ai.time_to_check_status < state.time()
 			{
 				let rot_speed = moveable.rot_speed;
-				let speed = moveable.speed;
+				let speed = moveable.speed * speed_factor;
 				let mut new_dir_vel = None;
 				let mut new_vel = None;
 				let mut do_attack = false;
@@ -3464,13 +4525,7 @@ impl Map
 
 				if play_sound
 				{
-					state.sfx.play_positional_sound(
-						&ai.sound,
-						pos.pos.xz(),
-						sound_camera,
-						self.camera_anchor.dir,
-						1.,
-					)?;
+					self.play_sound_at(state, &ai.sound, id, pos.pos.xz(), 1.)?;
 				}
 
 				vel.dir_vel = new_dir_vel.unwrap_or(0.);
@@ -3546,17 +4601,39 @@ impl Map
 				.map(|a| a.active)
 				.unwrap_or(true)
 			{
-				state.sfx.play_positional_sound(
-					&sound.sound,
-					pos.pos.xz(),
-					sound_camera,
-					self.camera_anchor.dir,
-					sound.volume,
-				)?;
+				self.play_sound_at(state, &sound.sound, id, pos.pos.xz(), sound.volume)?;
 				to_die.push((true, id));
 			}
 		}
 
+		// Ambient sound
+		for (id, (pos, ambient_sound)) in self
+			.world
+			.query::<(&components::Position, &components::AmbientSound)>()
+			.iter()
+		{
+			if self
+				.world
+				.get::<&components::Active>(id)
+				.map(|a| a.active)
+				.unwrap_or(true)
+			{
+				state.sfx.play_loop(
+					id.to_bits().get(),
+					&ambient_sound.sound,
+					sfx::SoundCategory::Ambience,
+					sfx::SoundPlacement {
+						sound_pos: pos.pos.xz(),
+						camera_pos: sound_camera,
+						camera_dir: self.camera_anchor.dir,
+						occluded: self.is_sound_occluded(id, pos.pos.xz(), sound_camera),
+					},
+					ambient_sound.volume,
+					ambient_sound.radius,
+				)?;
+			}
+		}
+
 		// Player start
 		if self.want_spawn
 		{
@@ -3575,57 +4652,180 @@ impl Map
 				let health = self.saved_health.clone();
 				let weapon_set = self.saved_weapon_set.clone();
 				let dir = pos.dir;
-				let player_class = self.player_class;
+				let player_class = self.player_class.clone();
 				spawn_fns.push((
 					true,
 					Box::new(move |_, world| {
-						spawn_player(point_pos, dir, player_class, health, weapon_set, world)
+						spawn_player(
+							point_pos,
+							dir,
+							&player_class,
+							health,
+							weapon_set,
+							None,
+							world,
+						)
 					}),
 				));
 			}
-			self.message.clear();
+			self.messages.dismiss();
 			self.ui_state = UIState::Regular;
 			self.want_spawn = false;
 		}
 
 		// Area trigger
 		let mut activate = vec![];
-		for (id, area_trigger) in self.world.query::<&components::AreaTrigger>().iter()
+		for (id, area_trigger) in self.world.query::<&mut components::AreaTrigger>().iter()
 		{
 			if self
 				.world
 				.get::<&components::Active>(id)
 				.map(|a| a.active)?
 			{
-				let entries = grid.query_rect(area_trigger.start, area_trigger.end, |entry| {
-					if let Ok(team) = self.world.get::<&components::Team>(entry.inner.id)
+				let (start, end) = area_trigger.shape.bounds();
+				let entries = grid.query_rect(start, end, |entry| {
+					let passes_filter = match area_trigger.filter
 					{
-						*team == components::Team::Player
-					}
-					else
+						components::TriggerFilter::Any => true,
+						components::TriggerFilter::Vehicle => self
+							.world
+							.get::<&components::Vehicle>(entry.inner.id)
+							.is_ok(),
+						filter =>
+						{
+							let team = match filter
+							{
+								components::TriggerFilter::Player => components::Team::Player,
+								components::TriggerFilter::Monster => components::Team::Monster,
+								_ => components::Team::Neutral,
+							};
+							self.world
+								.get::<&components::Team>(entry.inner.id)
+								.map(|t| *t == team)
+								.unwrap_or(false)
+						}
+					};
+					if !passes_filter
 					{
-						false
+						return false;
 					}
+					area_trigger.shape.contains(entry.inner.pos.xz())
 				});
-				if !entries.is_empty()
+				let occupied = !entries.is_empty();
+				if occupied && !area_trigger.occupied
 				{
-					for target in &area_trigger.targets
+					if state.time() >= area_trigger.time_to_rearm
 					{
-						if let Some(&entity) = self.named_entities.get(target)
+						push_targets(
+							&area_trigger.targets,
+							&self.named_entities,
+							&self.world,
+							&mut activate,
+						);
+						if let Some(name) = self.named_entities.name_of(id)
 						{
-							if self.world.contains(entity)
-							{
-								activate.push(entity);
-							}
+							self.script_events
+								.push(script::Event::AreaEntered(name.clone()));
+						}
+						area_trigger.occupied = true;
+						area_trigger.time_to_rearm =
+							state.time() + area_trigger.rearm_delay.unwrap_or(0.);
+						if area_trigger.rearm_delay.is_none()
+							&& area_trigger.exit_targets.is_empty()
+						{
+							to_die.push((true, id));
 						}
 					}
-					to_die.push((true, id));
 				}
+				else if !occupied && area_trigger.occupied
+				{
+					push_targets(
+						&area_trigger.exit_targets,
+						&self.named_entities,
+						&self.world,
+						&mut activate,
+					);
+					if let Some(name) = self.named_entities.name_of(id)
+					{
+						self.script_events
+							.push(script::Event::AreaExited(name.clone()));
+					}
+					area_trigger.occupied = false;
+					if area_trigger.rearm_delay.is_none()
+					{
+						to_die.push((true, id));
+					}
+				}
+			}
+		}
+
+		// Objective
+		for (_, (objective, active)) in self
+			.world
+			.query::<(&mut components::Objective, &components::Active)>()
+			.iter()
+		{
+			// Objectives without a counter are just reminders.
+			if objective.complete || objective.counter.is_empty()
+			{
+				continue;
+			}
+			let mut matched = false;
+			let mut complete = true;
+			for entity in self.named_entities.get(&objective.counter)
+			{
+				if let Ok(counter) = self.world.get::<&components::Counter>(entity)
+				{
+					objective.progress = Some((
+						utils::min(counter.count, counter.max_count),
+						counter.max_count,
+					));
+					complete &= counter.count >= counter.max_count;
+					matched = true;
+				}
+				else if let Ok(flag) = self.world.get::<&components::Active>(entity)
+				{
+					complete &= flag.active;
+					matched = true;
+				}
+			}
+			// Counters despawn once they're reached, so fall back on the last count we saw.
+			if !matched
+			{
+				if let Some((count, max_count)) = objective.progress
+				{
+					complete = count >= max_count;
+					matched = true;
+				}
+			}
+			if !active.active
+			{
+				continue;
+			}
+			if matched && complete
+			{
+				if let Some((_, max_count)) = objective.progress
+				{
+					objective.progress = Some((max_count, max_count));
+				}
+				objective.complete = true;
+				push_targets(
+					&objective.targets,
+					&self.named_entities,
+					&self.world,
+					&mut activate,
+				);
+				self.messages.push(messages::Message {
+					speaker: "".to_string(),
+					text: format!("OBJECTIVE COMPLETE: {}", objective.text),
+					color: messages::default_color(),
+					duration: 3.,
+				});
 			}
 		}
 
 		// Counter
-		for (id, counter) in self.world.query::<&components::Counter>().iter()
+		for (id, counter) in self.world.query::<&mut components::Counter>().iter()
 		{
 			if self
 				.world
@@ -3634,18 +4834,25 @@ impl Map
 			{
 				if counter.count >= counter.max_count
 				{
-					for target in &counter.targets
+					push_targets(
+						&counter.targets,
+						&self.named_entities,
+						&self.world,
+						&mut activate,
+					);
+					if let Some(name) = self.named_entities.name_of(id)
 					{
-						if let Some(&entity) = self.named_entities.get(target)
-						{
-							if self.world.contains(entity)
-							{
-								dbg!("activating", entity);
-								activate.push(entity);
-							}
-						}
+						self.script_events
+							.push(script::Event::CounterReached(name.clone()));
+					}
+					if counter.repeat
+					{
+						counter.count = 0;
+					}
+					else
+					{
+						to_die.push((true, id));
 					}
-					to_die.push((true, id));
 				}
 			}
 		}
@@ -3658,33 +4865,12 @@ impl Map
 				.get::<&components::Active>(id)
 				.map(|a| a.active)?
 			{
-				if next_level.next_level.is_empty()
-				{
-					return Ok(Some(game_state::NextScreen::Menu));
-				}
-				let mut saved_health = self.saved_health.clone();
-				let mut saved_weapon_set = self.saved_weapon_set.clone();
-				if let Ok(health) = self.world.get::<&components::Health>(self.player)
-				{
-					saved_health = (*health).clone();
-				}
-				if let Ok(weapon_set) = self.world.get::<&components::WeaponSet>(self.player)
-				{
-					saved_weapon_set = (*weapon_set).clone();
-				}
-
-				return Ok(Some(game_state::NextScreen::Game(
-					next_level.next_level.clone(),
-					self.player_class,
-					Some(saved_health),
-					Some(saved_weapon_set),
-					self.lifes,
-				)));
+				return Ok(Some(self.next_level_screen(state, &next_level.next_level)));
 			}
 		}
 
 		// Trigger
-		for (id, trigger) in self.world.query::<&components::Trigger>().iter()
+		for (id, trigger) in self.world.query::<&mut components::Trigger>().iter()
 		{
 			if self
 				.world
@@ -3693,47 +4879,403 @@ impl Map
 			{
 				if state.time() > trigger.time_to_trigger
 				{
-					for target in &trigger.targets
+					push_targets(
+						&trigger.targets,
+						&self.named_entities,
+						&self.world,
+						&mut activate,
+					);
+					if trigger.repeat > 0
 					{
-						if let Some(&entity) = self.named_entities.get(target)
-						{
-							if self.world.contains(entity)
-							{
-								activate.push(entity);
-							}
-						}
+						trigger.repeat -= 1;
+					}
+					if trigger.repeat == 0
+					{
+						to_die.push((true, id));
+					}
+					else
+					{
+						trigger.time_to_trigger = state.time() + trigger.delay;
 					}
-					to_die.push((true, id));
 				}
 			}
 		}
 
-		// Message
-		for (id, message) in self.world.query::<&components::Message>().iter()
+		// Relay
+		for (id, relay) in self.world.query::<&mut components::Relay>().iter()
 		{
 			if self
 				.world
 				.get::<&components::Active>(id)
 				.map(|a| a.active)?
 			{
-				if self.ui_state == UIState::Regular
+				let mut num_active = 0;
+				for input in &relay.inputs
 				{
-					self.message = vec![message.message.clone()];
-					self.time_to_hide_message = state.time() + 5.;
+					// A tag counts as active if all of its entities are.
+					let entities = self.named_entities.get(input);
+					if !entities.is_empty()
+						&& entities.iter().all(|&entity| {
+							self.world
+								.get::<&components::Active>(entity)
+								.map(|a| a.active)
+								.unwrap_or(false)
+						})
+					{
+						num_active += 1;
+					}
 				}
-				to_die.push((true, id));
-			}
-		}
-
-		let mut save = false;
-		for entity in activate
-		{
+				let output = match relay.gate
+				{
+					components::Gate::And => num_active == relay.inputs.len(),
+					components::Gate::Or => num_active > 0,
+					components::Gate::Not => num_active == 0,
+				};
+				// Only fire when the output turns on.
+				if output && !relay.output
+				{
+					push_targets(
+						&relay.targets,
+						&self.named_entities,
+						&self.world,
+						&mut activate,
+					);
+					if relay.once
+					{
+						to_die.push((true, id));
+					}
+				}
+				relay.output = output;
+			}
+		}
+
+		// Message
+		for (id, message) in self.world.query::<&components::Message>().iter()
+		{
+			if self
+				.world
+				.get::<&components::Active>(id)
+				.map(|a| a.active)?
+			{
+				self.messages.push(messages::Message {
+					speaker: message.speaker.clone(),
+					text: message.message.clone(),
+					color: message.color,
+					duration: message.duration,
+				});
+				to_die.push((true, id));
+			}
+		}
+
+		// Music intensity
+		let num_attackers = self
+			.world
+			.query::<&components::AI>()
+			.iter()
+			.filter(|(_, ai)| match ai.status
+			{
+				components::Status::Attacking(target) => target == self.player,
+				_ => false,
+			})
+			.count();
+		state
+			.sfx
+			.set_music_intensity(num_attackers as f32 / MAX_MUSIC_ATTACKERS);
+
+		// Music
+		for (id, music) in self.world.query::<&components::Music>().iter()
+		{
+			if self
+				.world
+				.get::<&components::Active>(id)
+				.map(|a| a.active)?
+			{
+				if state.options.play_music
+				{
+					state.sfx.set_playlist(music.playlist.clone());
+					state.sfx.play_music()?;
+				}
+				to_die.push((true, id));
+			}
+		}
+
+		// Cutscene
+		if self.cutscene.is_none()
+		{
+			for (id, cutscene) in self.world.query::<&components::Cutscene>().iter()
+			{
+				if self
+					.world
+					.get::<&components::Active>(id)
+					.map(|a| a.active)?
+				{
+					for page in &cutscene.pages
+					{
+						if !page.portrait.is_empty()
+						{
+							state.cache_bitmap(&page.portrait)?;
+						}
+					}
+					self.cutscene = Some(CutsceneState {
+						entity: id,
+						start_time: state.time(),
+						page: 0,
+						page_start_time: state.time(),
+						eye: cutscene.path[0],
+						target: cutscene.path[0],
+						next_page: false,
+						skip: false,
+					});
+					state.controls.set_frozen(true);
+					break;
+				}
+			}
+		}
+		let mut end_cutscene = false;
+		if let Some(cutscene_state) = self.cutscene.as_mut()
+		{
+			if state
+				.controls
+				.get_action_state(controls::Action::NextCutscenePage)
+				> 0.5
+			{
+				state
+					.controls
+					.clear_action_state(controls::Action::NextCutscenePage);
+				cutscene_state.next_page = true;
+			}
+			if state
+				.controls
+				.get_action_state(controls::Action::SkipCutscene)
+				> 0.5
+			{
+				state
+					.controls
+					.clear_action_state(controls::Action::SkipCutscene);
+				cutscene_state.skip = true;
+			}
+			if let Ok(cutscene) = self
+				.world
+				.get::<&components::Cutscene>(cutscene_state.entity)
+			{
+				if let Some(page) = cutscene.pages.get(cutscene_state.page)
+				{
+					if cutscene_state.next_page
+						|| state.time() > cutscene_state.page_start_time + page.duration
+					{
+						cutscene_state.page += 1;
+						cutscene_state.page_start_time = state.time();
+					}
+				}
+				cutscene_state.next_page = false;
+
+				// The camera takes the whole cutscene to move along the path, even if the dialogue
+				// is advanced early.
+				let duration = utils::max(
+					cutscene.duration,
+					cutscene.pages.iter().map(|p| p.duration).sum(),
+				);
+				let elapsed = state.time() - cutscene_state.start_time;
+				let f = if duration > 0.
+				{
+					(elapsed / duration) as f32
+				}
+				else
+				{
+					1.
+				};
+				let height = TILE / 2.2;
+				let pos = utils::point_along_path(&cutscene.path, f);
+				let eye = Point3::new(pos.x, height, pos.z);
+				let mut target = None;
+				for entity in self.named_entities.get(&cutscene.look_at)
+				{
+					if let Ok(look_at_pos) = self.world.get::<&components::Position>(entity)
+					{
+						target = Some(Point3::new(look_at_pos.pos.x, height, look_at_pos.pos.z));
+					}
+				}
+				let target = target.unwrap_or_else(|| {
+					let ahead = utils::point_along_path(&cutscene.path, f + 0.01);
+					if (ahead - pos).norm() > 1e-3
+					{
+						Point3::new(ahead.x, height, ahead.z)
+					}
+					else
+					{
+						cutscene_state.target
+					}
+				});
+				if (target - eye).xz().norm() > 1e-3
+				{
+					cutscene_state.target = target;
+				}
+				cutscene_state.eye = eye;
+
+				if cutscene_state.skip
+					|| cutscene_state.page >= cutscene.pages.len() && elapsed >= duration
+				{
+					push_targets(
+						&cutscene.targets,
+						&self.named_entities,
+						&self.world,
+						&mut activate,
+					);
+					to_die.push((true, cutscene_state.entity));
+					end_cutscene = true;
+				}
+			}
+			else
+			{
+				end_cutscene = true;
+			}
+		}
+		if end_cutscene
+		{
+			self.cutscene = None;
+			state.controls.set_frozen(false);
+		}
+
+		// Script
+		if let Some(script) = self.script.as_mut()
+		{
+			// Script errors are reported, but shouldn't end the game.
+			let mut errors = vec![];
+			let mut commands = vec![];
+			for event in self.script_events.drain(..)
+			{
+				match script.handle_event(&event)
+				{
+					Ok(new_commands) => commands.extend(new_commands),
+					Err(e) => errors.push(e.to_string()),
+				}
+			}
+			for command in commands
+			{
+				match command
+				{
+					script::Command::Spawn {
+						spawn,
+						x,
+						z,
+						dir,
+						name,
+					} =>
+					{
+						let spawn_fn = match str_to_spawn_fn(&spawn, state)
+						{
+							Ok(spawn_fn) => spawn_fn,
+							Err(e) =>
+							{
+								errors.push(e.to_string());
+								continue;
+							}
+						};
+						let entity =
+							spawn_fn(Point3::new(x, 0., z), dir, "", state, &mut self.world);
+						if !name.is_empty()
+						{
+							self.named_entities.insert(name, &[], entity);
+						}
+					}
+					script::Command::Activate(name) =>
+					{
+						for entity in self.named_entities.get(&name)
+						{
+							activate.push((entity, components::TargetMode::Activate));
+						}
+					}
+					script::Command::Deactivate(name) =>
+					{
+						for entity in self.named_entities.get(&name)
+						{
+							activate.push((entity, components::TargetMode::Deactivate));
+						}
+					}
+					script::Command::Toggle(name) =>
+					{
+						for entity in self.named_entities.get(&name)
+						{
+							activate.push((entity, components::TargetMode::Toggle));
+						}
+					}
+					script::Command::Message { speaker, text } =>
+					{
+						self.messages.push(messages::Message {
+							speaker: speaker,
+							text: text,
+							color: messages::default_color(),
+							duration: 5.,
+						});
+					}
+					script::Command::PlaySound(sound) =>
+					{
+						if let Err(e) = state.sfx.play_sound(&sound)
+						{
+							errors.push(e.to_string());
+						}
+					}
+					script::Command::SetMusic(playlist) =>
+					{
+						if state.options.play_music
+						{
+							state.sfx.set_playlist(playlist);
+							if let Err(e) = state.sfx.play_music()
+							{
+								errors.push(e.to_string());
+							}
+						}
+					}
+					script::Command::EndLevel(next_level) =>
+					{
+						return Ok(Some(self.next_level_screen(state, &next_level)));
+					}
+				}
+			}
+			for error in errors
+			{
+				println!("Script error: {}", error);
+				self.messages.push(messages::Message {
+					speaker: "SCRIPT ERROR".into(),
+					text: error.to_uppercase(),
+					color: Color::from_rgb_f(0.9, 0.4, 0.4),
+					duration: 5.,
+				});
+			}
+		}
+		else
+		{
+			self.script_events.clear();
+		}
+
+		self.messages.update(state.time());
+
+		let mut save = false;
+		for (entity, mode) in activate
+		{
+			if let Ok(mut counter) = self.world.get::<&mut components::Counter>(entity)
+			{
+				match mode
+				{
+					components::TargetMode::Increment => counter.count += 1,
+					components::TargetMode::Decrement => counter.count -= 1,
+					components::TargetMode::Reset => counter.count = 0,
+					_ => (),
+				}
+			}
 			if let Ok(mut active) = self.world.get::<&mut components::Active>(entity)
 			{
 				dbg!("activating", entity, active.active);
-				active.active = !active.active;
+				let was_active = active.active;
+				match mode
+				{
+					components::TargetMode::Toggle => active.active = !active.active,
+					components::TargetMode::Activate => active.active = true,
+					components::TargetMode::Deactivate => active.active = false,
+					_ => (),
+				}
 
-				if active.active
+				if active.active && !was_active
 				{
 					if self.world.get::<&components::PlayerStart>(entity).is_ok()
 					{
@@ -3779,7 +5321,7 @@ impl Map
 			{
 				for target in &deleter.targets
 				{
-					if let Some(&entity) = self.named_entities.get(target)
+					for entity in self.named_entities.get(target)
 					{
 						if self.world.contains(entity)
 						{
@@ -3801,12 +5343,21 @@ impl Map
 		}
 
 		// On-death effects
+		let mut camera_shake = 0.;
 		for &(trigger_on_death, id) in &to_die
 		{
 			if !trigger_on_death
 			{
 				continue;
 			}
+			if self.world.get::<&components::Health>(id).is_ok()
+			{
+				if let Some(name) = self.named_entities.name_of(id)
+				{
+					self.script_events
+						.push(script::Event::EntityDied(name.clone()));
+				}
+			}
 			if let Ok(pos) = self.world.get::<&components::Position>(id)
 			{
 				let point_pos = pos.pos.clone();
@@ -3839,6 +5390,11 @@ impl Map
 								push_strength,
 							} =>
 							{
+								let camera_dist =
+									(pos.pos.xz() - self.camera_anchor.pos.xz()).norm();
+								camera_shake += push_strength / 100.
+									* utils::max(0., 1. - camera_dist / (4. * radius));
+
 								let entries = grid.query_rect(
 									Point2::new(pos.pos.x - radius, pos.pos.z - radius),
 									Point2::new(pos.pos.x + radius, pos.pos.z + radius),
@@ -3868,20 +5424,25 @@ impl Map
 										.get::<&mut components::Health>(entry.inner.id)?;
 									if health.damage(damage, 1.)
 									{
-										if let components::DamageType::Cold(amount) =
-											damage.damage_type
+										// Blasts can't keep something frozen indefinitely.
+										let refreeze = matches!(
+											damage.damage_type,
+											components::DamageType::Cold(_)
+										) && self
+											.world
+											.get::<&components::StatusEffects>(entry.inner.id)
+											.map(|s| s.chill >= 1.)
+											.unwrap_or(false);
+										if !refreeze
 										{
-											if let Ok(mut freezable) =
-												self.world.get::<&mut components::Freezable>(
-													entry.inner.id,
-												)
-											{
-												if freezable.amount < 1.
-												{
-													freezable.amount =
-														utils::min(2., freezable.amount + amount);
-												}
-											}
+											inflict_damage_effects(
+												damage,
+												1.,
+												&health,
+												entry.inner.id,
+												state.time(),
+												&self.world,
+											);
 										}
 
 										if let (Ok(other_pos), Ok(solid), Ok(mut other_vel)) = (
@@ -3921,7 +5482,7 @@ impl Map
 							}
 							components::DeathEffect::IncrementCounter { target } =>
 							{
-								if let Some(&entity) = self.named_entities.get(&target)
+								for entity in self.named_entities.get(&target)
 								{
 									if self.world.contains(entity)
 									{
@@ -3929,20 +5490,14 @@ impl Map
 											self.world.get::<&mut components::Counter>(entity)
 										{
 											counter.count += 1;
-											dbg!(target, counter.count);
+											dbg!(&target, counter.count);
 										}
 									}
 								}
 							}
 							components::DeathEffect::PlaySound { sound, volume } =>
 							{
-								state.sfx.play_positional_sound(
-									&sound,
-									pos.pos.xz(),
-									sound_camera,
-									self.camera_anchor.dir,
-									volume,
-								)?;
+								self.play_sound_at(state, &sound, id, pos.pos.xz(), volume)?;
 							}
 						}
 					}
@@ -3961,6 +5516,8 @@ impl Map
 			}
 		}
 
+		self.add_camera_shake(camera_shake);
+
 		for (new_player, spawn_fn) in spawn_fns
 		{
 			let entity = spawn_fn(state, &mut self.world);
@@ -3972,27 +5529,60 @@ impl Map
 
 		if !self.world.get::<&components::Health>(self.player).is_ok()
 		{
-			if self.ui_state != UIState::InMenu
+			if self.ui_state == UIState::Regular
 			{
-				if self.lifes > 0
+				let text = if self.lifes > 0
 				{
 					self.ui_state = UIState::DeadHaveLives;
-					self.message = vec!["YOU HAVE FALLEN".into(), "PRESS (R) TO RESPAWN".into()];
-					self.time_to_hide_message = -1.;
+					"YOU HAVE FALLEN\nPRESS (R) TO RESPAWN"
 				}
 				else
 				{
 					self.ui_state = UIState::DeadForReal;
-					self.message = vec!["YOU HAVE DIED".into(), "PRESS (ESC) TO QUIT".into()];
-					self.time_to_hide_message = -1.;
-				}
+					"YOU HAVE DIED\nPRESS (ESC) TO QUIT"
+				};
+				self.messages.interrupt(messages::Message {
+					speaker: "".into(),
+					text: text.into(),
+					color: messages::default_color(),
+					duration: f64::INFINITY,
+				});
+			}
+		}
+
+		// Camera shake from damage taken.
+		let mut player_health = None;
+		if let Ok(health) = self.world.get::<&components::Health>(self.player)
+		{
+			player_health = Some((self.player, health.health + health.armour));
+		}
+		if let (Some((old_player, old_health)), Some((player, health))) =
+			(self.last_player_health, player_health)
+		{
+			if old_player == player && health < old_health
+			{
+				self.add_camera_shake((old_health - health) / 50.);
 			}
 		}
+		self.last_player_health = player_health;
+		self.camera_shake = utils::max(0., self.camera_shake - 1.5 * utils::DT);
 
 		// Update camera anchor.
 		if let Ok(player_pos) = self.world.get::<&components::Position>(self.player)
 		{
-			self.camera_anchor = *player_pos;
+			let smooth = state.options.camera_smoothing
+				&& self.world.get::<&components::Vehicle>(self.player).is_ok();
+			if smooth
+			{
+				let f = 1. - (-6. * utils::DT).exp();
+				self.camera_anchor.pos += f * (player_pos.pos - self.camera_anchor.pos);
+				self.camera_anchor.dir +=
+					f * utils::angle_diff(self.camera_anchor.dir, player_pos.dir);
+			}
+			else
+			{
+				self.camera_anchor = *player_pos;
+			}
 		}
 
 		to_die.sort();
@@ -4014,16 +5604,18 @@ impl Map
 		state.core.clear_depth_buffer(1.);
 
 		state.core.set_depth_test(Some(DepthFunction::Less));
+		let projection =
+			utils::projection_transform(self.display_width, self.display_height, state.options.fov);
 		state
 			.core
-			.use_projection_transform(&utils::mat4_to_transform(self.projection.into_inner()));
+			.use_projection_transform(&utils::mat4_to_transform(projection.into_inner()));
 		unsafe {
 			al_set_render_state(ALLEGRO_ALPHA_TEST_RS, 1);
 			al_set_render_state(ALLEGRO_ALPHA_TEST_VALUE, 128);
 			al_set_render_state(ALLEGRO_ALPHA_FUNCTION, ALLEGRO_RENDER_GREATER as i32);
 		}
 
-		let camera = self.make_camera();
+		let camera = self.make_camera(state);
 
 		state
 			.core
@@ -4041,6 +5633,10 @@ impl Map
 			.query::<(&components::Position, &components::Drawable)>()
 			.iter()
 		{
+			if id == self.player && state.options.camera_mode == game_state::CameraMode::FirstPerson
+			{
+				continue;
+			}
 			let time_offset = self
 				.world
 				.get::<&components::CreationTime>(id)
@@ -4049,11 +5645,14 @@ impl Map
 
 			let mut color = Color::from_rgb_f(1., 1., 1.);
 			let mut f = 1.;
-			if let Ok(freezable) = self.world.get::<&components::Freezable>(id)
+			if let Ok(status_effects) = self.world.get::<&components::StatusEffects>(id)
 			{
-				if freezable.is_frozen()
+				if let Some((r, g, b)) = status_effects.tint()
+				{
+					color = Color::from_rgb_f(r, g, b);
+				}
+				if status_effects.is_incapacitated()
 				{
-					color = Color::from_rgb_f(0.5, 0.5, 1.);
 					f = 0.;
 				}
 			}
@@ -4101,31 +5700,109 @@ impl Map
 			);
 		}
 
-		let ortho_mat = Matrix4::new_orthographic(
-			0.,
-			self.display_width as f32,
-			self.display_height as f32,
-			0.,
-			-1.,
-			1.,
-		);
+		utils::use_2d_projection(&state.core, self.display_width, self.display_height);
 
-		unsafe {
-			gl::Disable(gl::CULL_FACE);
+		if self.cutscene.is_none()
+		{
+			self.draw_hud(state);
 		}
-		state
-			.core
-			.use_projection_transform(&utils::mat4_to_transform(ortho_mat));
-		state.core.use_transform(&Transform::identity());
-		state.core.set_depth_test(None);
-		unsafe {
-			al_set_render_state(ALLEGRO_ALPHA_TEST_RS, 0);
+
+		let mut num_lines = 0;
+		if let Some(message) = self.messages.current()
+		{
+			let width = utils::min(self.display_width - 32., messages::MAX_WIDTH);
+			let mut lines = vec![];
+			if !message.speaker.is_empty()
+			{
+				lines.push((message.speaker.clone(), Color::from_rgb_f(0.8, 0.8, 0.5)));
+			}
+			for line in messages::wrap_text(&state.ui_font, &message.text, width)
+			{
+				lines.push((line, message.color));
+			}
+			for (line, color) in lines
+			{
+				state.core.draw_text(
+					&state.ui_font,
+					color,
+					self.display_width / 2.,
+					16. + 24. * num_lines as f32,
+					FontAlign::Centre,
+					&line,
+				);
+				num_lines += 1;
+			}
 		}
 
+		if self.cutscene.is_none()
+		{
+			let objectives = self.objective_lines();
+			for (i, (line, color)) in objectives.iter().rev().enumerate()
+			{
+				state.core.draw_text(
+					&state.ui_font,
+					*color,
+					16.,
+					self.display_height - 104. - 24. * i as f32,
+					FontAlign::Left,
+					line,
+				);
+			}
+		}
+
+		if let Some(cutscene_state) = &self.cutscene
+		{
+			self.draw_cutscene(cutscene_state, state);
+			num_lines = 1;
+		}
+
+		if state.options.captions && self.subscreens.is_empty()
+		{
+			for (i, caption) in state.sfx.get_captions().iter().enumerate()
+			{
+				state.core.draw_text(
+					&state.ui_font,
+					Color::from_rgb_f(0.8, 0.8, 1.),
+					self.display_width / 2.,
+					16. + 24. * (num_lines + i) as f32,
+					FontAlign::Centre,
+					&caption.text,
+				);
+			}
+		}
+
+		if let Some(subscreen) = self.subscreens.last()
+		{
+			state.prim.draw_filled_rectangle(
+				0.,
+				0.,
+				self.display_width,
+				self.display_height,
+				Color::from_rgba_f(0., 0., 0., 0.5),
+			);
+			subscreen.draw(state);
+		}
+
+		Ok(())
+	}
+
+	fn draw_hud(&self, state: &game_state::GameState)
+	{
 		let c_ui = Color::from_rgb_f(0.8, 0.8, 0.5);
 		let dw = 96.;
 
-		if let Ok(health) = self.world.get::<&components::Health>(self.player)
+		let vehicle = self.world.get::<&components::Vehicle>(self.player).ok();
+		// In a vehicle, the pilot's health is kept aside from the vehicle's.
+		let pilot_health = match &vehicle
+		{
+			Some(vehicle) => vehicle.saved_health.clone(),
+			None => self
+				.world
+				.get::<&components::Health>(self.player)
+				.ok()
+				.map(|h| (*h).clone()),
+		};
+		if let Some(health) = pilot_health
 		{
 			state.core.draw_text(
 				&state.ui_font,
@@ -4182,62 +5859,157 @@ impl Map
 			);
 		}
 
-		if let Ok(weapon_set) = self.world.get::<&components::WeaponSet>(self.player)
+		if vehicle.is_some()
 		{
-			let inactive_color = Color::from_rgb_f(0.8, 0.8, 0.8);
-			let active_color = Color::from_rgb_f(1., 1., 1.);
-			if let Some(weapon) = weapon_set.weapons.get(&components::WeaponType::OrbGun)
+			if let Ok(health) = self.world.get::<&components::Health>(self.player)
 			{
-				let color = if weapon_set.cur_weapon == components::WeaponType::OrbGun
+				state.core.draw_text(
+					&state.ui_font,
+					c_ui,
+					3. * dw + 48.,
+					self.display_height - 72.,
+					FontAlign::Centre,
+					"VEHICLE",
+				);
+
+				state.core.draw_text(
+					&state.number_font,
+					Color::from_rgb_f(0.8, 0.6, 0.4),
+					3. * dw + 48.,
+					self.display_height - 64.,
+					FontAlign::Centre,
+					&format!("{:.0}", health.health + health.armour),
+				);
+			}
+		}
+
+		if let Ok(status_effects) = self.world.get::<&components::StatusEffects>(self.player)
+		{
+			let lh = state.ui_font.get_line_height() as f32;
+			for (i, effect) in status_effects.effects.iter().enumerate()
+			{
+				let (r, g, b) = effect.effect_type.tint();
+				let x = i as f32 * dw + 8.;
+				let y = self.display_height - 112.;
+				let w = dw - 16.;
+				// Blinks when about to run out.
+				let time_left = effect.until - state.time();
+				if time_left < 1. && (time_left * 8.) as i32 % 2 == 0
 				{
-					active_color
+					continue;
 				}
-				else
-				{
-					inactive_color
-				};
-				if weapon.selectable
+				state.prim.draw_filled_rectangle(
+					x,
+					y,
+					x + w,
+					y + lh + 8.,
+					Color::from_rgba_f(0.5 * r, 0.5 * g, 0.5 * b, 0.5),
+				);
+				state
+					.prim
+					.draw_rectangle(x, y, x + w, y + lh + 8., Color::from_rgb_f(r, g, b), 2.);
+				state.core.draw_text(
+					&state.ui_font,
+					Color::from_rgb_f(r, g, b),
+					x + w / 2.,
+					y + 4.,
+					FontAlign::Centre,
+					effect.effect_type.label(),
+				);
+			}
+		}
+
+		if let Ok(ability) = self.world.get::<&components::Ability>(self.player)
+		{
+			state.core.draw_text(
+				&state.ui_font,
+				c_ui,
+				3. * dw + 48.,
+				self.display_height - 72.,
+				FontAlign::Centre,
+				"ABILITY",
+			);
+
+			let readiness = ability.readiness(state.time());
+			let color = if ability.ready(state.time())
+			{
+				Color::from_rgb_f(0.8, 0.8, 0.4)
+			}
+			else
+			{
+				Color::from_rgb_f(0.5, 0.5, 0.5)
+			};
+			let x = 3. * dw + 8.;
+			let y = self.display_height - 48.;
+			let w = dw - 16.;
+			state
+				.prim
+				.draw_filled_rectangle(x, y, x + w * readiness, y + 16., color);
+			state.prim.draw_rectangle(x, y, x + w, y + 16., c_ui, 2.);
+		}
+
+		let inactive_color = Color::from_rgb_f(0.8, 0.8, 0.8);
+		let active_color = Color::from_rgb_f(1., 1., 1.);
+		if let (Some(vehicle), Ok(weapon_set)) = (
+			&vehicle,
+			self.world.get::<&components::WeaponSet>(self.player),
+		)
+		{
+			for (i, (weapon_type, ammo_name)) in vehicle.weapons.iter().enumerate()
+			{
+				if let Some(weapon) = weapon_set.weapons.get(weapon_type)
 				{
+					let color = if weapon_set.cur_weapon == *weapon_type
+					{
+						active_color
+					}
+					else
+					{
+						inactive_color
+					};
+					let x = self.display_width - 48. - i as f32 * dw;
+
 					state.core.draw_text(
 						&state.ui_font,
 						c_ui,
-						self.display_width - 48.,
+						x,
 						self.display_height - 72.,
 						FontAlign::Centre,
-						"STARS",
+						ammo_name,
 					);
 
 					state.core.draw_text(
 						&state.number_font,
 						color,
-						self.display_width - 48.,
+						x,
 						self.display_height - 64.,
 						FontAlign::Centre,
 						&format!("{}", weapon.ammo),
 					);
 				}
 			}
-
-			if let Some(weapon) = weapon_set.weapons.get(&components::WeaponType::BuggyGun)
+		}
+		else if let Ok(weapon_set) = self.world.get::<&components::WeaponSet>(self.player)
+		{
+			if let Some(weapon) = weapon_set.weapons.get(&components::WeaponType::OrbGun)
 			{
+				let color = if weapon_set.cur_weapon == components::WeaponType::OrbGun
+				{
+					active_color
+				}
+				else
+				{
+					inactive_color
+				};
 				if weapon.selectable
 				{
-					let color = if weapon_set.cur_weapon == components::WeaponType::BuggyGun
-					{
-						active_color
-					}
-					else
-					{
-						inactive_color
-					};
-
 					state.core.draw_text(
 						&state.ui_font,
 						c_ui,
 						self.display_width - 48.,
 						self.display_height - 72.,
 						FontAlign::Centre,
-						"BULLETS",
+						"STARS",
 					);
 
 					state.core.draw_text(
@@ -4297,11 +6069,7 @@ impl Map
 						inactive_color
 					};
 
-					let ammo_name = match self.player_class
-					{
-						game_state::PlayerClass::Santa => "BULLETS",
-						game_state::PlayerClass::Reindeer => "ROCKETS",
-					};
+					let ammo_name = &self.player_class.ammo_name;
 
 					state.core.draw_text(
 						&state.ui_font,
@@ -4323,36 +6091,121 @@ impl Map
 				}
 			}
 		}
+	}
 
-		if state.time() < self.time_to_hide_message
-			|| self.time_to_hide_message < 0. && !self.message.is_empty()
-		{
-			for (i, msg) in self.message.iter().enumerate()
-			{
-				state.core.draw_text(
-					&state.ui_font,
-					Color::from_rgb_f(1., 1., 0.8),
-					self.display_width / 2.,
-					16. + 24. * i as f32,
-					FontAlign::Centre,
-					msg,
-				);
-			}
-		}
+	fn draw_cutscene(&self, cutscene_state: &CutsceneState, state: &game_state::GameState)
+	{
+		let black = Color::from_rgb_f(0., 0., 0.);
+		let bar_height = self.display_height / 6.;
+		state
+			.prim
+			.draw_filled_rectangle(0., 0., self.display_width, bar_height, black);
+		state.prim.draw_filled_rectangle(
+			0.,
+			self.display_height - bar_height,
+			self.display_width,
+			self.display_height,
+			black,
+		);
+		state.core.draw_text(
+			&state.ui_font,
+			Color::from_rgb_f(0.5, 0.5, 0.5),
+			self.display_width - 16.,
+			bar_height - 24.,
+			FontAlign::Right,
+			&format!(
+				"({}) CONTINUE  ({}) SKIP",
+				state
+					.controls
+					.get_controls()
+					.get_action_string(controls::Action::NextCutscenePage)
+					.to_uppercase(),
+				state
+					.controls
+					.get_controls()
+					.get_action_string(controls::Action::SkipCutscene)
+					.to_uppercase(),
+			),
+		);
 
-		if let Some(subscreen) = self.subscreens.last()
+		let cutscene = match self
+			.world
+			.get::<&components::Cutscene>(cutscene_state.entity)
 		{
-			state.prim.draw_filled_rectangle(
+			Ok(cutscene) => cutscene,
+			Err(_) => return,
+		};
+		let page = match cutscene.pages.get(cutscene_state.page)
+		{
+			Some(page) => page,
+			None => return,
+		};
+		let portrait_size = bar_height - 16.;
+		let mut x = 16.;
+		let y = self.display_height - bar_height + 8.;
+		if let Some(bmp) = state.get_bitmap(&page.portrait)
+		{
+			state.core.draw_scaled_bitmap(
+				bmp,
 				0.,
 				0.,
-				self.display_width,
-				self.display_height,
-				Color::from_rgba_f(0., 0., 0., 0.5),
+				bmp.get_width() as f32,
+				bmp.get_height() as f32,
+				x,
+				y,
+				portrait_size,
+				portrait_size,
+				Flag::zero(),
 			);
-			subscreen.draw(state);
+			x += portrait_size + 16.;
+		}
+		let mut line_y = y;
+		if !page.speaker.is_empty()
+		{
+			state.core.draw_text(
+				&state.ui_font,
+				Color::from_rgb_f(0.8, 0.8, 0.5),
+				x,
+				line_y,
+				FontAlign::Left,
+				&page.speaker,
+			);
+			line_y += 24.;
 		}
+		for line in &page.lines
+		{
+			state.core.draw_text(
+				&state.ui_font,
+				Color::from_rgb_f(1., 1., 0.8),
+				x,
+				line_y,
+				FontAlign::Left,
+				line,
+			);
+			line_y += 24.;
+		}
+	}
 
-		Ok(())
+	pub fn display_failed(&mut self, state: &mut game_state::GameState, error: &str)
+	{
+		for subscreen in &mut self.subscreens
+		{
+			subscreen.display_failed(state, error);
+		}
+	}
+
+	pub fn resize(&mut self, display_width: f32, display_height: f32)
+	{
+		let offset = Vector2::new(
+			(display_width - self.display_width) / 2.,
+			(display_height - self.display_height) / 2.,
+		);
+		self.display_width = display_width;
+		self.display_height = display_height;
+		for subscreen in &mut self.subscreens
+		{
+			subscreen.resize(display_width, display_height, offset);
+		}
 	}
 
 	pub fn input(
@@ -4362,10 +6215,12 @@ impl Map
 		state.controls.decode_event(event);
 		if self.ui_state == UIState::InMenu
 		{
-			if let Some(action) = self
-				.subscreens
-				.last_mut()
-				.and_then(|s| s.input(state, event))
+			let action = match self.subscreens.last_mut()
+			{
+				Some(s) => s.input(state, event)?,
+				None => None,
+			};
+			if let Some(action) = action
 			{
 				match action
 				{
@@ -4382,12 +6237,22 @@ impl Map
 						self.subscreens.pop().unwrap();
 					}
 					ui::Action::MainMenu => return Ok(Some(game_state::NextScreen::Menu)),
+					ui::Action::MessageLog =>
+					{
+						self.subscreens
+							.push(ui::SubScreen::MessageLog(ui::MessageLog::new(
+								state,
+								&self.messages,
+								self.display_width,
+								self.display_height,
+							)));
+					}
 					_ => (),
 				}
 			}
 			if self.subscreens.is_empty()
 			{
-				self.ui_state = UIState::Regular;
+				self.ui_state = self.ui_state_before_menu;
 				state.paused = false;
 				state.hide_mouse = true;
 			}
@@ -4415,9 +6280,11 @@ impl Map
 						state.sfx.play_sound("data/ui2.ogg").unwrap();
 						self.subscreens
 							.push(ui::SubScreen::InGameMenu(ui::InGameMenu::new(
+								self.objective_lines(),
 								self.display_width,
 								self.display_height,
 							)));
+						self.ui_state_before_menu = self.ui_state;
 						self.ui_state = UIState::InMenu;
 						state.paused = true;
 						state.hide_mouse = false;
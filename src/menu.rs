@@ -4,8 +4,7 @@ use crate::{components, controls, game_state, map, utils};
 
 use allegro::*;
 use allegro_font::*;
-use allegro_sys::*;
-use nalgebra::Matrix4;
+use nalgebra::Vector2;
 
 pub struct Menu
 {
@@ -24,15 +23,23 @@ impl Menu
 	{
 		if state.options.play_music
 		{
-			state.sfx.set_music_file("data/evil_minded.mod");
+			state.sfx.set_playlist(vec!["data/evil_minded.mod".into()]);
 			state.sfx.play_music()?;
+			state.sfx.set_combat_music(None)?;
 		}
 		state.hide_mouse = false;
 		state.paused = false;
 
 		state.cache_bitmap("data/main_menu.png")?;
-		state.cache_sprite_sheet("data/santa.cfg")?;
-		state.cache_sprite_sheet("data/reindeer.cfg")?;
+		for sprite_sheet in state
+			.player_classes
+			.classes
+			.iter()
+			.map(|c| c.sprite_sheet.clone())
+			.collect::<Vec<_>>()
+		{
+			state.cache_sprite_sheet(&sprite_sheet)?;
+		}
 		state.sfx.cache_sample("data/ui1.ogg")?;
 		state.sfx.cache_sample("data/ui2.ogg")?;
 
@@ -40,6 +47,7 @@ impl Menu
 			display_width: display_width,
 			display_height: display_height,
 			subscreens: vec![SubScreen::MainMenu(MainMenu::new(
+				state,
 				display_width,
 				display_height,
 			))],
@@ -47,11 +55,34 @@ impl Menu
 		})
 	}
 
+	pub fn display_failed(&mut self, state: &mut game_state::GameState, error: &str)
+	{
+		for subscreen in &mut self.subscreens
+		{
+			subscreen.display_failed(state, error);
+		}
+	}
+
+	pub fn resize(&mut self, display_width: f32, display_height: f32)
+	{
+		let offset = Vector2::new(
+			(display_width - self.display_width) / 2.,
+			(display_height - self.display_height) / 2.,
+		);
+		self.display_width = display_width;
+		self.display_height = display_height;
+		for subscreen in &mut self.subscreens
+		{
+			subscreen.resize(display_width, display_height, offset);
+		}
+	}
+
 	pub fn input(
 		&mut self, event: &Event, state: &mut game_state::GameState,
 	) -> Result<Option<game_state::NextScreen>>
 	{
-		if let Some(action) = self.subscreens.last_mut().unwrap().input(state, event)
+		state.controls.decode_event(event);
+		if let Some(action) = self.subscreens.last_mut().unwrap().input(state, event)?
 		{
 			match action
 			{
@@ -68,6 +99,7 @@ impl Menu
 					self.subscreens.pop().unwrap();
 				}
 				Action::Quit => return Ok(Some(game_state::NextScreen::Quit)),
+				Action::Credits => return Ok(Some(game_state::NextScreen::Credits)),
 				Action::SelectLevel(name) =>
 				{
 					self.next_level = name.clone();
@@ -80,13 +112,18 @@ impl Menu
 				}
 				Action::SelectCharacter(character) =>
 				{
-					return Ok(Some(game_state::NextScreen::Game(
-						self.next_level.clone(),
-						character,
-						None,
-						None,
-						3,
-					)));
+					let game = game_state::NextScreen::Game(game_state::CampaignState::new(
+						&self.next_level,
+						&character,
+					));
+					if let Some(intro) = state.campaign.intro(&self.next_level)
+					{
+						return Ok(Some(game_state::NextScreen::Slideshow(
+							intro.to_string(),
+							Box::new(game),
+						)));
+					}
+					return Ok(Some(game));
 				}
 				_ => (),
 			}
@@ -96,26 +133,7 @@ impl Menu
 
 	pub fn draw(&mut self, state: &game_state::GameState) -> Result<()>
 	{
-		let ortho_mat = Matrix4::new_orthographic(
-			0.,
-			self.display_width as f32,
-			self.display_height as f32,
-			0.,
-			-1.,
-			1.,
-		);
-
-		unsafe {
-			gl::Disable(gl::CULL_FACE);
-		}
-		state
-			.core
-			.use_projection_transform(&utils::mat4_to_transform(ortho_mat));
-		state.core.use_transform(&Transform::identity());
-		state.core.set_depth_test(None);
-		unsafe {
-			al_set_render_state(ALLEGRO_ALPHA_TEST_RS, 0);
-		}
+		utils::use_2d_projection(&state.core, self.display_width, self.display_height);
 		state.core.clear_to_color(Color::from_rgb_f(0., 0., 0.));
 		self.subscreens.last().unwrap().draw(state);
 
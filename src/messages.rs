@@ -0,0 +1,172 @@
+use allegro::*;
+use allegro_font::*;
+use std::collections::VecDeque;
+
+pub const MAX_HISTORY: usize = 100;
+// Messages are wrapped to this width, or the display width if it's narrower.
+pub const MAX_WIDTH: f32 = 800.;
+
+pub fn default_color() -> Color
+{
+	Color::from_rgb_f(1., 1., 0.8)
+}
+
+#[derive(Clone, Debug)]
+pub struct Message
+{
+	pub speaker: String,
+	pub text: String,
+	pub color: Color,
+	pub duration: f64,
+}
+
+pub struct MessageQueue
+{
+	queue: VecDeque<Message>,
+	current: Option<Message>,
+	time_to_hide: f64,
+	history: VecDeque<Message>,
+}
+
+impl MessageQueue
+{
+	pub fn new() -> Self
+	{
+		Self {
+			queue: VecDeque::new(),
+			current: None,
+			time_to_hide: 0.,
+			history: VecDeque::new(),
+		}
+	}
+
+	pub fn push(&mut self, message: Message)
+	{
+		self.queue.push_back(message);
+	}
+
+	// Replaces the current message with this one on the next update.
+	pub fn interrupt(&mut self, message: Message)
+	{
+		self.queue.push_front(message);
+		self.current = None;
+	}
+
+	pub fn dismiss(&mut self)
+	{
+		self.current = None;
+	}
+
+	pub fn update(&mut self, time: f64)
+	{
+		if self.current.is_some() && time < self.time_to_hide
+		{
+			return;
+		}
+		self.current = self.queue.pop_front();
+		if let Some(message) = &self.current
+		{
+			self.time_to_hide = time + message.duration;
+			self.history.push_back(message.clone());
+			if self.history.len() > MAX_HISTORY
+			{
+				self.history.pop_front();
+			}
+		}
+	}
+
+	pub fn current(&self) -> Option<&Message>
+	{
+		self.current.as_ref()
+	}
+
+	pub fn history(&self) -> impl DoubleEndedIterator<Item = &Message>
+	{
+		self.history.iter()
+	}
+}
+
+pub fn wrap_text(font: &Font, text: &str, width: f32) -> Vec<String>
+{
+	wrap_text_with(text, width, |s| font.get_text_width(s) as f32)
+}
+
+fn wrap_text_with(text: &str, width: f32, text_width: impl Fn(&str) -> f32) -> Vec<String>
+{
+	let mut lines = vec![];
+	for paragraph in text.lines()
+	{
+		let mut line = String::new();
+		for word in paragraph.split_whitespace()
+		{
+			let candidate = if line.is_empty()
+			{
+				word.to_string()
+			}
+			else
+			{
+				format!("{} {}", line, word)
+			};
+			// Words too long for a line get one to themselves.
+			if !line.is_empty() && text_width(&candidate) > width
+			{
+				lines.push(line);
+				line = word.to_string();
+			}
+			else
+			{
+				line = candidate;
+			}
+		}
+		if !line.is_empty()
+		{
+			lines.push(line);
+		}
+	}
+	lines
+}
+
+#[test]
+fn test_message_queue()
+{
+	let message = |text: &str| Message {
+		speaker: "".into(),
+		text: text.into(),
+		color: Color::from_rgb_f(1., 1., 1.),
+		duration: 2.,
+	};
+	let mut queue = MessageQueue::new();
+	queue.push(message("A"));
+	queue.push(message("B"));
+	assert_eq!(queue.history().count(), 0);
+
+	queue.update(0.);
+	assert_eq!(queue.history().count(), 1);
+	assert_eq!(queue.current().unwrap().text, "A");
+	queue.update(1.);
+	assert_eq!(queue.current().unwrap().text, "A");
+	queue.update(2.);
+	assert_eq!(queue.current().unwrap().text, "B");
+	queue.update(4.);
+	assert!(queue.current().is_none());
+	assert_eq!(queue.history().count(), 2);
+}
+
+#[test]
+fn test_wrap_text()
+{
+	let text_width = |s: &str| s.len() as f32;
+	assert_eq!(
+		wrap_text_with("THE QUICK BROWN FOX", 10., text_width),
+		vec!["THE QUICK", "BROWN FOX"]
+	);
+	assert_eq!(
+		wrap_text_with("A VERYLONGWORD B", 5., text_width),
+		vec!["A", "VERYLONGWORD", "B"]
+	);
+	assert_eq!(
+		wrap_text_with("YOU HAVE DIED\nPRESS (ESC)", 100., text_width),
+		vec!["YOU HAVE DIED", "PRESS (ESC)"]
+	);
+	assert!(wrap_text_with("  ", 10., text_width).is_empty());
+}
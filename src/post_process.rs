@@ -0,0 +1,143 @@
+use crate::error::Result;
+use crate::game_state::{GameState, PostProcess};
+use allegro::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+// PICO-8 palette.
+const RETRO_PALETTE: [[f32; 3]; 16] = [
+	[0.0, 0.0, 0.0],
+	[0.114, 0.169, 0.325],
+	[0.494, 0.145, 0.325],
+	[0.0, 0.529, 0.3176],
+	[0.671, 0.322, 0.212],
+	[0.373, 0.341, 0.310],
+	[0.761, 0.765, 0.780],
+	[1.0, 0.945, 0.910],
+	[1.0, 0.0, 0.302],
+	[1.0, 0.639, 0.0],
+	[1.0, 0.925, 0.153],
+	[0.0, 0.894, 0.212],
+	[0.161, 0.678, 1.0],
+	[0.514, 0.463, 0.612],
+	[1.0, 0.467, 0.659],
+	[1.0, 0.800, 0.667],
+];
+
+// Shaders live as long as the display that created them, so each one is only built once.
+pub struct PostProcessor
+{
+	shaders: HashMap<String, Option<Weak<Shader>>>,
+	filename: Option<String>,
+}
+
+impl PostProcessor
+{
+	pub fn new(state: &GameState, display: &mut Display) -> Self
+	{
+		let mut post_processor = Self {
+			shaders: HashMap::new(),
+			filename: None,
+		};
+		post_processor.update(state, display);
+		post_processor
+	}
+
+	pub fn update(&mut self, state: &GameState, display: &mut Display)
+	{
+		let filename = match state.options.post_process
+		{
+			PostProcess::Off =>
+			{
+				self.filename = None;
+				return;
+			}
+			PostProcess::Crt => "data/crt.glsl",
+			PostProcess::RetroPalette => "data/retro_palette.glsl",
+			PostProcess::Colorblind => "data/colorblind.glsl",
+			PostProcess::Custom => &state.options.custom_post_process,
+		};
+		if !self.shaders.contains_key(filename)
+		{
+			// Shaders are optional, so just draw the buffer unfiltered if this fails. Failures
+			// are remembered too, so a broken shader isn't rebuilt every time it's selected.
+			let shader = match load_shader(state, display, filename)
+			{
+				Ok(shader) => Some(shader),
+				Err(e) =>
+				{
+					println!("{}", e);
+					None
+				}
+			};
+			self.shaders.insert(filename.to_string(), shader);
+		}
+		self.filename = Some(filename.to_string());
+	}
+
+	pub fn draw(&self, state: &GameState, buffer: &Bitmap, x: f32, y: f32, w: f32, h: f32)
+	{
+		let shader = self
+			.filename
+			.as_ref()
+			.and_then(|f| self.shaders.get(f))
+			.and_then(|s| s.as_ref())
+			.and_then(|s| s.upgrade());
+		let shader_used = match &shader
+		{
+			Some(shader) => state.core.use_shader(Some(shader)).is_ok(),
+			None => false,
+		};
+		let bw = buffer.get_width() as f32;
+		let bh = buffer.get_height() as f32;
+		if shader_used
+		{
+			// Custom shaders needn't use all of these.
+			state
+				.core
+				.set_shader_uniform("buffer_size", &[[bw, bh]][..])
+				.ok();
+			state
+				.core
+				.set_shader_uniform("output_size", &[[w, h]][..])
+				.ok();
+			state
+				.core
+				.set_shader_uniform("time", &[state.core.get_time() as f32][..])
+				.ok();
+			state
+				.core
+				.set_shader_uniform("palette", &RETRO_PALETTE[..])
+				.ok();
+		}
+		state
+			.core
+			.draw_scaled_bitmap(buffer, 0., 0., bw, bh, x, y, w, h, Flag::zero());
+		if shader_used
+		{
+			state.core.use_shader(None).ok();
+		}
+	}
+}
+
+fn load_shader(state: &GameState, display: &mut Display, filename: &str) -> Result<Weak<Shader>>
+{
+	let shader = display
+		.create_shader(ShaderPlatform::GLSL)
+		.map_err(|_| "Couldn't create shader".to_string())?;
+	let shader_ref: Arc<Shader> = shader.upgrade().unwrap();
+	let vertex_source = state
+		.core
+		.get_default_shader_source(ShaderPlatform::GLSL, ShaderType::Vertex)
+		.ok_or_else(|| "Couldn't get the default vertex shader".to_string())?;
+	shader_ref
+		.attach_shader_source(ShaderType::Vertex, Some(&vertex_source))
+		.map_err(|e| format!("Couldn't attach vertex shader:\n{}", e))?;
+	shader_ref
+		.attach_shader_source_file(ShaderType::Pixel, filename)
+		.map_err(|e| format!("Couldn't load {}:\n{}", filename, e))?;
+	shader_ref
+		.build()
+		.map_err(|e| format!("Couldn't build {}:\n{}", filename, e))?;
+	Ok(shader)
+}
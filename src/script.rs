@@ -0,0 +1,211 @@
+use crate::error::Result;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub enum Event
+{
+	LevelStart,
+	EntityDied(String),
+	AreaEntered(String),
+	AreaExited(String),
+	CounterReached(String),
+	ItemPickedUp(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Command
+{
+	Spawn
+	{
+		spawn: String,
+		x: f32,
+		z: f32,
+		dir: f32,
+		name: String,
+	},
+	Activate(String),
+	Deactivate(String),
+	Toggle(String),
+	Message
+	{
+		speaker: String,
+		text: String,
+	},
+	PlaySound(String),
+	SetMusic(Vec<String>),
+	EndLevel(String),
+}
+
+fn register_api(engine: &mut rhai::Engine, commands: &Rc<RefCell<Vec<Command>>>)
+{
+	// Positions are in world units, the same as TMX pixels scaled to tiles. `spawn` is a reserved
+	// word in Rhai.
+	let c = commands.clone();
+	engine.register_fn(
+		"spawn_entity",
+		move |spawn: &str, x: rhai::FLOAT, z: rhai::FLOAT, dir: rhai::FLOAT, name: &str| {
+			c.borrow_mut().push(Command::Spawn {
+				spawn: spawn.to_string(),
+				x: x as f32,
+				z: z as f32,
+				dir: dir as f32,
+				name: name.to_string(),
+			})
+		},
+	);
+	let c = commands.clone();
+	engine.register_fn("activate", move |name: &str| {
+		c.borrow_mut().push(Command::Activate(name.to_string()))
+	});
+	let c = commands.clone();
+	engine.register_fn("deactivate", move |name: &str| {
+		c.borrow_mut().push(Command::Deactivate(name.to_string()))
+	});
+	let c = commands.clone();
+	engine.register_fn("toggle", move |name: &str| {
+		c.borrow_mut().push(Command::Toggle(name.to_string()))
+	});
+	let c = commands.clone();
+	engine.register_fn("message", move |text: &str| {
+		c.borrow_mut().push(Command::Message {
+			speaker: "".to_string(),
+			text: text.to_string(),
+		})
+	});
+	let c = commands.clone();
+	engine.register_fn("message", move |speaker: &str, text: &str| {
+		c.borrow_mut().push(Command::Message {
+			speaker: speaker.to_string(),
+			text: text.to_string(),
+		})
+	});
+	let c = commands.clone();
+	engine.register_fn("play_sound", move |sound: &str| {
+		c.borrow_mut().push(Command::PlaySound(sound.to_string()))
+	});
+	let c = commands.clone();
+	engine.register_fn("set_music", move |music: &str| {
+		c.borrow_mut()
+			.push(Command::SetMusic(vec![music.to_string()]))
+	});
+	let c = commands.clone();
+	engine.register_fn("set_music", move |playlist: rhai::Array| {
+		c.borrow_mut().push(Command::SetMusic(
+			playlist.into_iter().map(|m| m.to_string()).collect(),
+		))
+	});
+	let c = commands.clone();
+	engine.register_fn("end_level", move |next_level: &str| {
+		c.borrow_mut()
+			.push(Command::EndLevel(next_level.to_string()))
+	});
+}
+
+pub struct Script
+{
+	engine: rhai::Engine,
+	ast: rhai::AST,
+	scope: rhai::Scope<'static>,
+	commands: Rc<RefCell<Vec<Command>>>,
+}
+
+impl Script
+{
+	pub fn new(filename: &str) -> Result<Self>
+	{
+		let mut engine = rhai::Engine::new();
+		let commands = Rc::new(RefCell::new(vec![]));
+		register_api(&mut engine, &commands);
+
+		let ast = engine
+			.compile_file(Path::new(filename).to_path_buf())
+			.map_err(|e| format!("Couldn't load {}:\n{}", filename, e))?;
+		let mut scope = rhai::Scope::new();
+		// Runs the top-level statements, so scripts can set up their variables.
+		engine
+			.run_ast_with_scope(&mut scope, &ast)
+			.map_err(|e| format!("Error running {}:\n{}", filename, e))?;
+
+		Ok(Self {
+			engine: engine,
+			ast: ast,
+			scope: scope,
+			commands: commands,
+		})
+	}
+
+	pub fn handle_event(&mut self, event: &Event) -> Result<Vec<Command>>
+	{
+		let (fn_name, args) = match event
+		{
+			Event::LevelStart => ("on_level_start", vec![]),
+			Event::EntityDied(name) => ("on_entity_died", vec![name.clone()]),
+			Event::AreaEntered(name) => ("on_area_entered", vec![name.clone()]),
+			Event::AreaExited(name) => ("on_area_exited", vec![name.clone()]),
+			Event::CounterReached(name) => ("on_counter_reached", vec![name.clone()]),
+			Event::ItemPickedUp(item) => ("on_item_picked_up", vec![item.clone()]),
+		};
+		// Scripts only need to define the handlers they care about.
+		let has_fn = self
+			.ast
+			.iter_functions()
+			.any(|f| f.name == fn_name && f.params.len() == args.len());
+		if has_fn
+		{
+			let args: Vec<rhai::Dynamic> = args.into_iter().map(|a| a.into()).collect();
+			// Return values are ignored.
+			let result: std::result::Result<rhai::Dynamic, _> =
+				self.engine
+					.call_fn(&mut self.scope, &self.ast, fn_name, args);
+			if let Err(e) = result
+			{
+				// Don't let a half-run handler's commands leak into the next event.
+				self.commands.borrow_mut().clear();
+				return Err(format!("Error in {}:\n{}", fn_name, e).into());
+			}
+		}
+		Ok(self.commands.borrow_mut().drain(..).collect())
+	}
+}
+
+#[test]
+fn test_script()
+{
+	let mut script = Script::new("data/example_script.rhai").unwrap();
+
+	let commands = script.handle_event(&Event::LevelStart).unwrap();
+	assert_eq!(commands.len(), 2);
+	assert!(script
+		.handle_event(&Event::EntityDied("cat|2".into()))
+		.unwrap()
+		.is_empty());
+	let commands = script
+		.handle_event(&Event::EntityDied("boss|1".into()))
+		.unwrap();
+	assert_eq!(commands.len(), 2);
+	match &commands[0]
+	{
+		Command::Activate(name) => assert_eq!(name, "#exit_door"),
+		_ => panic!(),
+	}
+	assert_eq!(script.scope.get_value::<rhai::INT>("kills"), Some(2));
+	let commands = script
+		.handle_event(&Event::AreaEntered("ambush|2".into()))
+		.unwrap();
+	match &commands[0]
+	{
+		Command::Spawn { spawn, name, .. } =>
+		{
+			assert_eq!(spawn, "cat");
+			assert_eq!(name, "ambusher");
+		}
+		_ => panic!(),
+	}
+	// No handler for this one.
+	assert!(script
+		.handle_event(&Event::ItemPickedUp("health".into()))
+		.unwrap()
+		.is_empty());
+}
@@ -1,14 +1,175 @@
 use crate::error::Result;
 use crate::utils;
+use crate::utils::DT;
 use nalgebra::{Point2, Vector2};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::time::Instant;
 
 use allegro::*;
 use allegro_acodec::*;
 use allegro_audio::*;
 
 use rand::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rolloff
+{
+	InverseSquare,
+	Inverse,
+	Linear,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DistanceModel
+{
+	pub rolloff: Rolloff,
+	// Distance at which the sound plays at full volume.
+	pub ref_dist: f32,
+	pub max_dist: f32,
+	// Volume multiplier when there's a wall between the sound and the listener.
+	pub occluded_volume: f32,
+}
+
+impl Default for DistanceModel
+{
+	fn default() -> Self
+	{
+		Self {
+			rolloff: Rolloff::InverseSquare,
+			ref_dist: 200.,
+			max_dist: 2000.,
+			occluded_volume: 0.35,
+		}
+	}
+}
+
+impl DistanceModel
+{
+	pub fn attenuation(&self, dist: f32, occluded: bool) -> f32
+	{
+		if dist >= self.max_dist
+		{
+			return 0.;
+		}
+		let dist = utils::max(dist, 1e-3);
+		let attenuation = match self.rolloff
+		{
+			Rolloff::InverseSquare => self.ref_dist * self.ref_dist / (dist * dist),
+			Rolloff::Inverse => self.ref_dist / dist,
+			Rolloff::Linear =>
+			{
+				1. - (dist - self.ref_dist) / utils::max(self.max_dist - self.ref_dist, 1e-3)
+			}
+		};
+		let attenuation = utils::clamp(attenuation, 0., 1.);
+		if occluded
+		{
+			attenuation * self.occluded_volume
+		}
+		else
+		{
+			attenuation
+		}
+	}
+}
+
+// Where a sound is heard from.
+#[derive(Copy, Clone, Debug)]
+pub struct SoundPlacement
+{
+	pub sound_pos: Point2<f32>,
+	pub camera_pos: Point2<f32>,
+	pub camera_dir: f32,
+	pub occluded: bool,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SoundCategory
+{
+	Weapons,
+	Monsters,
+	Ui,
+	Ambience,
+	Voice,
+}
+
+impl SoundCategory
+{
+	pub fn all() -> [SoundCategory; 5]
+	{
+		[
+			SoundCategory::Weapons,
+			SoundCategory::Monsters,
+			SoundCategory::Ui,
+			SoundCategory::Ambience,
+			SoundCategory::Voice,
+		]
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SoundDesc
+{
+	pub sound: String,
+	pub category: SoundCategory,
+	// Higher priority sounds can stop lower priority ones when there are too many playing.
+	pub priority: i32,
+	// Minimum seconds between two plays of this sound.
+	pub cooldown: f32,
+	// Shown on screen when captions are enabled, empty for none.
+	pub caption: String,
+	pub distance_model: DistanceModel,
+}
+
+impl Default for SoundDesc
+{
+	fn default() -> Self
+	{
+		Self {
+			sound: "".into(),
+			category: SoundCategory::Weapons,
+			priority: 0,
+			cooldown: 0.,
+			caption: "".into(),
+			distance_model: Default::default(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct SoundsDesc
+{
+	pub default: SoundDesc,
+	pub sounds: Vec<SoundDesc>,
+}
+
+const MAX_SOUNDS: usize = 50;
+const MUSIC_CROSSFADE: f64 = 2.;
+const CAPTION_DURATION: f32 = 3.;
+const MAX_CAPTIONS: usize = 4;
+
+pub struct Caption
+{
+	pub text: String,
+	time: Instant,
+}
+
+struct LoopingSound
+{
+	instance: SampleInstance,
+	touched: bool,
+}
+
+struct PlayingSound
+{
+	instance: SampleInstance,
+	priority: i32,
+}
 
 pub struct Sfx
 {
@@ -16,12 +177,27 @@ pub struct Sfx
 	acodec: AcodecAddon,
 	sink: Sink,
 	stream: Option<AudioStream>,
-	music_file: String,
-	sample_instances: Vec<SampleInstance>,
+	fading_stream: Option<AudioStream>,
+	// 0 when a crossfade starts, 1 when it's done.
+	music_fade: f32,
+	playlist: Vec<String>,
+	playlist_idx: usize,
+	// Looping track blended in as the combat intensity rises.
+	combat_stream: Option<AudioStream>,
+	music_intensity: f32,
+	target_music_intensity: f32,
+	sample_instances: Vec<PlayingSound>,
+	// Keyed by the emitter, dropped if not updated every tick.
+	loops: HashMap<u64, LoopingSound>,
 	exclusive_sounds: Vec<String>,
 	exclusive_instance: Option<SampleInstance>,
 	sfx_volume: f32,
 	music_volume: f32,
+	category_volumes: HashMap<SoundCategory, f32>,
+	default_sound_desc: SoundDesc,
+	sound_descs: HashMap<String, SoundDesc>,
+	last_played: HashMap<String, Instant>,
+	captions: Vec<Caption>,
 
 	samples: HashMap<String, Sample>,
 }
@@ -33,6 +209,14 @@ impl Sfx
 		let audio = AudioAddon::init(&core)?;
 		let acodec = AcodecAddon::init(&audio)?;
 		let sink = Sink::new(&audio).map_err(|_| "Couldn't create audio sink".to_string())?;
+		let sounds_desc: SoundsDesc = if std::path::Path::new("data/sounds.cfg").exists()
+		{
+			utils::load_config("data/sounds.cfg")?
+		}
+		else
+		{
+			Default::default()
+		};
 
 		let mut sfx = Sfx {
 			sfx_volume: 0.,
@@ -42,10 +226,26 @@ impl Sfx
 			sink: sink,
 			sample_instances: vec![],
 			stream: None,
+			fading_stream: None,
+			music_fade: 1.,
+			playlist: vec![],
+			playlist_idx: 0,
+			combat_stream: None,
+			music_intensity: 0.,
+			target_music_intensity: 0.,
 			exclusive_instance: None,
+			loops: HashMap::new(),
 			exclusive_sounds: vec![],
 			samples: HashMap::new(),
-			music_file: "".into(),
+			category_volumes: SoundCategory::all().iter().map(|&c| (c, 1.)).collect(),
+			default_sound_desc: sounds_desc.default,
+			sound_descs: sounds_desc
+				.sounds
+				.into_iter()
+				.map(|d| (d.sound.clone(), d))
+				.collect(),
+			last_played: HashMap::new(),
+			captions: vec![],
 		};
 		sfx.set_sfx_volume(sfx_volume);
 		sfx.set_music_volume(music_volume);
@@ -53,9 +253,10 @@ impl Sfx
 		Ok(sfx)
 	}
 
-	pub fn set_music_file(&mut self, music: &str)
+	pub fn set_playlist(&mut self, playlist: Vec<String>)
 	{
-		self.music_file = music.to_string();
+		self.playlist = playlist;
+		self.playlist_idx = 0;
 	}
 
 	pub fn cache_sample<'l>(&'l mut self, name: &str) -> Result<&'l Sample>
@@ -74,14 +275,16 @@ impl Sfx
 
 	pub fn update_sounds(&mut self) -> Result<()>
 	{
-		self.sample_instances.retain(|s| s.get_playing().unwrap());
-		if let Some(ref stream) = self.stream
+		self.sample_instances
+			.retain(|s| s.instance.get_playing().unwrap());
+		self.captions
+			.retain(|c| c.time.elapsed().as_secs_f32() < CAPTION_DURATION);
+		self.loops.retain(|_, l| l.touched);
+		for l in self.loops.values_mut()
 		{
-			if !stream.get_playing()
-			{
-				self.play_music()?
-			}
+			l.touched = false;
 		}
+		self.update_music()?;
 
 		if !self.exclusive_sounds.is_empty()
 		{
@@ -94,12 +297,14 @@ impl Sfx
 			{
 				let name = self.exclusive_sounds.pop().unwrap();
 				self.cache_sample(&name)?;
+				let volume =
+					self.sfx_volume * self.category_volume(self.sound_desc(&name).category);
 				let sample = self.samples.get(&name).unwrap();
 				let instance = self
 					.sink
 					.play_sample(
 						sample,
-						self.sfx_volume,
+						volume,
 						None,
 						thread_rng().gen_range(0.9..1.1),
 						Playmode::Once,
@@ -112,57 +317,215 @@ impl Sfx
 		Ok(())
 	}
 
-	pub fn play_sound(&mut self, name: &str) -> Result<()>
+	pub fn sound_desc(&self, name: &str) -> &SoundDesc
+	{
+		self.sound_descs
+			.get(name)
+			.unwrap_or(&self.default_sound_desc)
+	}
+
+	pub fn category_volume(&self, category: SoundCategory) -> f32
+	{
+		self.category_volumes[&category]
+	}
+
+	pub fn set_category_volume(&mut self, category: SoundCategory, volume: f32)
+	{
+		self.category_volumes.insert(category, volume);
+	}
+
+	fn play_instance(&mut self, name: &str, volume: f32, pan: Option<f32>) -> Result<()>
 	{
 		self.cache_sample(name)?;
+		let desc = self.sound_desc(name);
+		let priority = desc.priority;
+		let cooldown = desc.cooldown;
+		let volume = volume * self.category_volume(desc.category);
+
+		let now = Instant::now();
+		if let Some(last_played) = self.last_played.get(name)
+		{
+			if (now - *last_played).as_secs_f32() < cooldown
+			{
+				return Ok(());
+			}
+		}
+
+		if self.sample_instances.len() >= MAX_SOUNDS
+		{
+			// Steal the oldest of the lowest priority sounds.
+			let mut steal_idx: Option<usize> = None;
+			for (i, sound) in self.sample_instances.iter().enumerate()
+			{
+				let best_priority = steal_idx
+					.map(|j| self.sample_instances[j].priority)
+					.unwrap_or(priority);
+				if sound.priority < best_priority
+				{
+					steal_idx = Some(i);
+				}
+			}
+			match steal_idx
+			{
+				Some(i) =>
+				{
+					self.sample_instances.remove(i);
+				}
+				None => return Ok(()),
+			}
+		}
+
 		let sample = self.samples.get(name).unwrap();
 		let instance = self
 			.sink
 			.play_sample(
 				sample,
-				self.sfx_volume,
-				None,
+				volume,
+				pan,
 				thread_rng().gen_range(0.9..1.1),
 				Playmode::Once,
 			)
 			.map_err(|_| "Couldn't play sound".to_string())?;
-		self.sample_instances.push(instance);
+		self.sample_instances.push(PlayingSound {
+			instance: instance,
+			priority: priority,
+		});
+		self.last_played.insert(name.to_string(), now);
 		Ok(())
 	}
 
+	pub fn play_sound(&mut self, name: &str) -> Result<()>
+	{
+		let caption = &self.sound_desc(name).caption;
+		if !caption.is_empty()
+		{
+			let text = format!("[{}]", caption);
+			self.add_caption(text);
+		}
+		self.play_instance(name, self.sfx_volume, None)
+	}
+
+	// Returns the gain before the volume settings are applied, and the pan.
+	fn positional_gain_pan(
+		&self, distance_model: &DistanceModel, placement: SoundPlacement, volume: f32,
+	) -> (f32, f32)
+	{
+		let sound_pos = placement.sound_pos;
+		let camera_pos = placement.camera_pos;
+		let dist = (sound_pos - camera_pos).norm();
+		let attenuation = distance_model.attenuation(dist, placement.occluded);
+		let diff = sound_pos - camera_pos;
+		let diff = diff / (diff.norm() + 1e-3);
+
+		let dir_vec = utils::dir_vec3(placement.camera_dir).xz();
+		let left = Vector2::new(-dir_vec.y, dir_vec.x);
+		let pan = utils::clamp(left.dot(&diff), -1., 1.);
+		(volume * attenuation, pan)
+	}
+
+	fn sfx_gain(&self, gain: f32) -> f32
+	{
+		self.sfx_volume * utils::clamp(self.sfx_volume * gain, 0., 1.)
+	}
+
 	pub fn play_positional_sound(
-		&mut self, name: &str, sound_pos: Point2<f32>, camera_pos: Point2<f32>, dir: f32,
-		volume: f32,
+		&mut self, name: &str, placement: SoundPlacement, volume: f32,
 	) -> Result<()>
 	{
-		self.cache_sample(name)?;
+		let distance_model = self.sound_desc(name).distance_model;
+		let (gain, pan) = self.positional_gain_pan(&distance_model, placement, volume);
+		if gain <= 0.
+		{
+			return Ok(());
+		}
 
-		if self.sample_instances.len() < 50
+		// Captions go by what's audible in the world, so they still show with the sound muted.
+		let caption = &self.sound_desc(name).caption;
+		if !caption.is_empty()
 		{
-			let sample = self.samples.get(name).unwrap();
+			let diff = placement.sound_pos - placement.camera_pos;
+			let forward = utils::dir_vec3(placement.camera_dir).xz().dot(&diff);
+			let direction = if pan < -0.5
+			{
+				"LEFT"
+			}
+			else if pan > 0.5
+			{
+				"RIGHT"
+			}
+			else if forward < 0.
+			{
+				"BEHIND"
+			}
+			else
+			{
+				"AHEAD"
+			};
+			let text = format!("[{} - {}]", caption, direction);
+			self.add_caption(text);
+		}
+
+		let volume = self.sfx_gain(gain);
+		if volume <= 0.
+		{
+			return Ok(());
+		}
+		self.play_instance(name, volume, Some(pan))
+	}
+
+	fn add_caption(&mut self, text: String)
+	{
+		self.captions.retain(|c| c.text != text);
+		self.captions.push(Caption {
+			text: text,
+			time: Instant::now(),
+		});
+		if self.captions.len() > MAX_CAPTIONS
+		{
+			self.captions.remove(0);
+		}
+	}
 
-			let dist = (sound_pos - camera_pos).norm();
-			let volume = self.sfx_volume
-				* utils::clamp(self.sfx_volume * volume * 40000. / (dist * dist), 0., 1.);
-			let diff = sound_pos - camera_pos;
-			let diff = diff / (diff.norm() + 1e-3);
+	pub fn get_captions(&self) -> &[Caption]
+	{
+		&self.captions
+	}
 
-			let dir_vec = utils::dir_vec3(dir).xz();
-			let left = Vector2::new(-dir_vec.y, dir_vec.x);
-			let pan = utils::clamp(left.dot(&diff), -1., 1.);
+	// Starts or updates a looping sound. Must be called every tick to keep it playing.
+	pub fn play_loop(
+		&mut self, key: u64, name: &str, category: SoundCategory, placement: SoundPlacement,
+		volume: f32, radius: f32,
+	) -> Result<()>
+	{
+		let distance_model = DistanceModel {
+			rolloff: Rolloff::Linear,
+			ref_dist: radius / 4.,
+			max_dist: radius,
+			occluded_volume: self.sound_desc(name).distance_model.occluded_volume,
+		};
+		let (gain, pan) = self.positional_gain_pan(&distance_model, placement, volume);
+		let volume = self.sfx_gain(gain) * self.category_volume(category);
 
+		if !self.loops.contains_key(&key)
+		{
+			self.cache_sample(name)?;
+			let sample = self.samples.get(name).unwrap();
 			let instance = self
 				.sink
-				.play_sample(
-					sample,
-					volume,
-					Some(pan),
-					thread_rng().gen_range(0.9..1.1),
-					Playmode::Once,
-				)
+				.play_sample(sample, volume, Some(pan), 1., Playmode::Loop)
 				.map_err(|_| "Couldn't play sound".to_string())?;
-			self.sample_instances.push(instance);
+			self.loops.insert(
+				key,
+				LoopingSound {
+					instance: instance,
+					touched: true,
+				},
+			);
 		}
+		let looping_sound = self.loops.get_mut(&key).unwrap();
+		looping_sound.instance.set_gain(volume).unwrap();
+		looping_sound.instance.set_pan(Some(pan)).unwrap();
+		looping_sound.touched = true;
 		Ok(())
 	}
 
@@ -172,28 +535,153 @@ impl Sfx
 		Ok(())
 	}
 
+	// Starts the current playlist track, crossfading from whatever is playing.
 	pub fn play_music(&mut self) -> Result<()>
 	{
-		let mut new_stream = AudioStream::load(&self.audio, &self.music_file)
-			.map_err(|_| format!("Couldn't load {}", self.music_file))?;
+		if self.playlist.is_empty()
+		{
+			return Ok(());
+		}
+		let music_file = &self.playlist[self.playlist_idx % self.playlist.len()];
+		let mut new_stream = AudioStream::load(&self.audio, music_file)
+			.map_err(|_| format!("Couldn't load {}", music_file))?;
 		new_stream.attach(&mut self.sink).unwrap();
 		//~ new_stream.set_playmode(Playmode::Loop).unwrap();
-		new_stream.set_gain(self.music_volume).unwrap();
+		self.fading_stream = self.stream.take();
+		self.music_fade = if self.fading_stream.is_some() { 0. } else { 1. };
 		self.stream = Some(new_stream);
+		self.update_music_gain();
 		Ok(())
 	}
 
-	pub fn set_music_volume(&mut self, new_volume: f32)
+	pub fn set_combat_music(&mut self, music_file: Option<&str>) -> Result<()>
 	{
-		self.music_volume = 0.2 * new_volume;
+		self.combat_stream = None;
+		self.music_intensity = 0.;
+		self.target_music_intensity = 0.;
+		if let Some(music_file) = music_file
+		{
+			let mut new_stream = AudioStream::load(&self.audio, music_file)
+				.map_err(|_| format!("Couldn't load {}", music_file))?;
+			new_stream.attach(&mut self.sink).unwrap();
+			new_stream.set_playmode(Playmode::Loop).unwrap();
+			self.combat_stream = Some(new_stream);
+			self.update_music_gain();
+		}
+		Ok(())
+	}
+
+	// 0 is calm, 1 is full combat.
+	pub fn set_music_intensity(&mut self, intensity: f32)
+	{
+		self.target_music_intensity = utils::clamp(intensity, 0., 1.);
+	}
+
+	fn update_music(&mut self) -> Result<()>
+	{
+		if self.music_intensity != self.target_music_intensity
+		{
+			// Ramp up quickly, but linger a bit after the fighting is over.
+			let diff = self.target_music_intensity - self.music_intensity;
+			let rate = if diff > 0. { 1. } else { 0.25 };
+			self.music_intensity += utils::clamp(diff, -rate * DT, rate * DT);
+			self.update_music_gain();
+		}
+
+		if self.music_fade < 1.
+		{
+			self.music_fade =
+				utils::min(1., self.music_fade + (DT as f64 / MUSIC_CROSSFADE) as f32);
+			if self.music_fade >= 1.
+			{
+				self.fading_stream = None;
+			}
+			self.update_music_gain();
+		}
+
+		let mut next_track = false;
+		if let Some(stream) = &self.stream
+		{
+			// Start crossfading into the next track a little before this one ends.
+			let near_end = match (stream.get_length_secs(), stream.get_position_secs())
+			{
+				(Ok(length), Ok(position)) if length > 2. * MUSIC_CROSSFADE =>
+				{
+					position > length - MUSIC_CROSSFADE
+				}
+				_ => false,
+			};
+			next_track = self.music_fade >= 1. && (near_end || !stream.get_playing());
+		}
+		if next_track
+		{
+			self.playlist_idx = (self.playlist_idx + 1) % utils::max(1, self.playlist.len());
+			self.play_music()?;
+		}
+		Ok(())
+	}
+
+	fn update_music_gain(&mut self)
+	{
+		let explore_volume = if let Some(stream) = self.combat_stream.as_mut()
+		{
+			stream
+				.set_gain(self.music_volume * self.music_intensity)
+				.unwrap();
+			1. - self.music_intensity
+		}
+		else
+		{
+			1.
+		};
 		if let Some(stream) = self.stream.as_mut()
 		{
-			stream.set_gain(self.music_volume).unwrap();
+			stream
+				.set_gain(self.music_volume * self.music_fade * explore_volume)
+				.unwrap();
+		}
+		if let Some(stream) = self.fading_stream.as_mut()
+		{
+			stream
+				.set_gain(self.music_volume * (1. - self.music_fade) * explore_volume)
+				.unwrap();
 		}
 	}
 
+	pub fn set_music_volume(&mut self, new_volume: f32)
+	{
+		self.music_volume = 0.2 * new_volume;
+		self.update_music_gain();
+	}
+
 	pub fn set_sfx_volume(&mut self, new_volume: f32)
 	{
 		self.sfx_volume = 0.2 * new_volume;
 	}
 }
+
+#[test]
+fn test_distance_model()
+{
+	let model = DistanceModel::default();
+	assert_eq!(model.attenuation(0., false), 1.);
+	assert_eq!(model.attenuation(model.ref_dist, false), 1.);
+	assert!((model.attenuation(2. * model.ref_dist, false) - 0.25).abs() < 1e-3);
+	assert_eq!(model.attenuation(model.max_dist, false), 0.);
+	assert!((model.attenuation(model.ref_dist, true) - model.occluded_volume).abs() < 1e-3);
+}
+
+#[test]
+fn test_distance_model_linear()
+{
+	let model = DistanceModel {
+		rolloff: Rolloff::Linear,
+		ref_dist: 100.,
+		max_dist: 300.,
+		occluded_volume: 0.5,
+	};
+	assert_eq!(model.attenuation(50., false), 1.);
+	assert!((model.attenuation(200., false) - 0.5).abs() < 1e-3);
+	assert!((model.attenuation(200., true) - 0.25).abs() < 1e-3);
+	assert_eq!(model.attenuation(300., false), 0.);
+}
@@ -0,0 +1,178 @@
+use crate::error::Result;
+use crate::{game_state, messages, utils};
+
+use allegro::*;
+use allegro_font::*;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Slide
+{
+	#[serde(default)]
+	pub image: String,
+	pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SlideshowDesc
+{
+	#[serde(default)]
+	pub music: String,
+	pub slides: Vec<Slide>,
+}
+
+pub struct Slideshow
+{
+	display_width: f32,
+	display_height: f32,
+	slides: Vec<Slide>,
+	page: usize,
+	next_screen: Option<game_state::NextScreen>,
+}
+
+impl Slideshow
+{
+	pub fn new(
+		state: &mut game_state::GameState, filename: &str, next_screen: game_state::NextScreen,
+		display_width: f32, display_height: f32,
+	) -> Result<Self>
+	{
+		let desc: SlideshowDesc = utils::load_config(filename)?;
+		if state.options.play_music && !desc.music.is_empty()
+		{
+			state.sfx.set_playlist(vec![desc.music.clone()]);
+			state.sfx.play_music()?;
+			state.sfx.set_combat_music(None)?;
+		}
+		state.hide_mouse = true;
+		state.paused = false;
+
+		for slide in &desc.slides
+		{
+			if !slide.image.is_empty()
+			{
+				state.cache_bitmap(&slide.image)?;
+			}
+		}
+		state.sfx.cache_sample("data/ui1.ogg")?;
+
+		Ok(Self {
+			display_width: display_width,
+			display_height: display_height,
+			slides: desc.slides,
+			page: 0,
+			next_screen: Some(next_screen),
+		})
+	}
+
+	pub fn resize(&mut self, display_width: f32, display_height: f32)
+	{
+		self.display_width = display_width;
+		self.display_height = display_height;
+	}
+
+	pub fn input(
+		&mut self, event: &Event, state: &mut game_state::GameState,
+	) -> Result<Option<game_state::NextScreen>>
+	{
+		state.controls.decode_event(event);
+		let skip = match event
+		{
+			Event::KeyDown { keycode, .. } => match keycode
+			{
+				KeyCode::Space | KeyCode::Enter =>
+				{
+					self.page += 1;
+					false
+				}
+				KeyCode::Escape => true,
+				_ => return Ok(None),
+			},
+			Event::MouseButtonDown { .. } =>
+			{
+				self.page += 1;
+				false
+			}
+			_ => return Ok(None),
+		};
+		state.sfx.play_sound("data/ui1.ogg")?;
+		if skip || self.page >= self.slides.len()
+		{
+			return Ok(self.next_screen.take());
+		}
+		Ok(None)
+	}
+
+	pub fn draw(&mut self, state: &game_state::GameState) -> Result<()>
+	{
+		utils::use_2d_projection(&state.core, self.display_width, self.display_height);
+		state.core.clear_to_color(Color::from_rgb_f(0., 0., 0.));
+
+		let slide = match self.slides.get(self.page)
+		{
+			Some(slide) => slide,
+			None => return Ok(()),
+		};
+
+		let lh = state.ui_font.get_line_height() as f32;
+		let lines = messages::wrap_text(
+			&state.ui_font,
+			&slide.text,
+			(self.display_width - 64.).min(messages::MAX_WIDTH),
+		);
+		let text_height = lines.len() as f32 * lh;
+
+		// The image sits above the text, shrunk if the two don't fit together.
+		let mut y = 32.;
+		if let Some(bmp) = state.get_bitmap(&slide.image)
+		{
+			let bw = bmp.get_width() as f32;
+			let bh = bmp.get_height() as f32;
+			let max_height = self.display_height - text_height - 3. * 32.;
+			let scale = (max_height / bh)
+				.min((self.display_width - 64.) / bw)
+				.min(1.);
+			state.core.draw_scaled_bitmap(
+				bmp,
+				0.,
+				0.,
+				bw,
+				bh,
+				((self.display_width - bw * scale) / 2.).floor(),
+				y,
+				(bw * scale).floor(),
+				(bh * scale).floor(),
+				Flag::zero(),
+			);
+			y += bh * scale + 32.;
+		}
+		else
+		{
+			y = (self.display_height - text_height) / 2.;
+		}
+
+		for line in &lines
+		{
+			state.core.draw_text(
+				&state.ui_font,
+				messages::default_color(),
+				self.display_width / 2.,
+				y,
+				FontAlign::Centre,
+				line,
+			);
+			y += lh;
+		}
+
+		state.core.draw_text(
+			&state.ui_font,
+			Color::from_rgb_f(0.5, 0.5, 0.5),
+			self.display_width - 16.,
+			self.display_height - 16. - lh,
+			FontAlign::Right,
+			"(SPACE) CONTINUE  (ESCAPE) SKIP",
+		);
+
+		Ok(())
+	}
+}
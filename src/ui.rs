@@ -1,5 +1,5 @@
 use crate::error::Result;
-use crate::{components, controls, game_state, map, utils};
+use crate::{components, controls, game_state, map, messages, sfx, utils, video};
 
 use allegro::*;
 use allegro_font::*;
@@ -12,15 +12,31 @@ pub enum Action
 	SelectMe,
 	MainMenu,
 	SelectLevel(String),
-	SelectCharacter(game_state::PlayerClass),
+	SelectCharacter(String),
+	Credits,
 	Quit,
 	Back,
 	Forward(fn(&mut game_state::GameState, f32, f32) -> SubScreen),
-	ToggleFullscreen,
+	WindowMode(usize),
+	DisplayMode(usize),
+	VsyncMethod(usize),
+	RenderResolution(usize),
+	ScalingMode(usize),
+	PostProcess(usize),
+	ApplyVideo,
+	KeepVideo,
+	RevertVideo,
 	ChangeInput(controls::Action, usize),
 	MouseSensitivity(f32),
 	MusicVolume(f32),
 	SfxVolume(f32),
+	CategoryVolume(sfx::SoundCategory, f32),
+	ToggleCaptions,
+	ToggleCameraMode,
+	Fov(f32),
+	ScreenShake(f32),
+	ToggleCameraSmoothing,
+	MessageLog,
 }
 
 impl Action
@@ -266,6 +282,7 @@ struct Slider
 	loc: Point2<f32>,
 	size: Vector2<f32>,
 	cur_pos: f32,
+	min_pos: f32,
 	max_pos: f32,
 	grabbed: bool,
 	selected: bool,
@@ -282,6 +299,7 @@ impl Slider
 			loc: Point2::new(x, y),
 			size: Vector2::new(w, h),
 			cur_pos: cur_pos,
+			min_pos: 0.,
 			max_pos: max_pos,
 			grabbed: false,
 			selected: false,
@@ -289,6 +307,18 @@ impl Slider
 		}
 	}
 
+	fn with_min(mut self, min_pos: f32) -> Self
+	{
+		self.min_pos = min_pos;
+		self.cur_pos = utils::max(min_pos, self.cur_pos);
+		self
+	}
+
+	fn range(&self) -> f32
+	{
+		self.max_pos - self.min_pos
+	}
+
 	fn width(&self) -> f32
 	{
 		self.size.x
@@ -311,7 +341,7 @@ impl Slider
 		};
 
 		let w = self.width();
-		let cursor_x = self.loc.x - w / 2. + w * self.cur_pos / self.max_pos;
+		let cursor_x = self.loc.x - w / 2. + w * (self.cur_pos - self.min_pos) / self.range();
 		let start_x = self.loc.x - w / 2.;
 		let end_x = self.loc.x + w / 2.;
 		if cursor_x - start_x > 16.
@@ -350,7 +380,7 @@ impl Slider
 				{
 					if self.grabbed
 					{
-						self.cur_pos = (x - start.x) / self.width() * self.max_pos;
+						self.cur_pos = self.min_pos + (x - start.x) / self.width() * self.range();
 						return Some((self.action_fn)(self.cur_pos));
 					}
 					else
@@ -374,7 +404,7 @@ impl Slider
 				{
 					state.sfx.play_sound("data/ui2.ogg").unwrap();
 					self.grabbed = true;
-					self.cur_pos = (x - start.x) / self.width() * self.max_pos;
+					self.cur_pos = self.min_pos + (x - start.x) / self.width() * self.range();
 					return Some((self.action_fn)(self.cur_pos));
 				}
 			}
@@ -386,10 +416,11 @@ impl Slider
 					{
 						KeyCode::Left =>
 						{
-							if self.cur_pos > 0.
+							if self.cur_pos > self.min_pos
 							{
 								state.sfx.play_sound("data/ui2.ogg").unwrap();
-								self.cur_pos = utils::max(0., self.cur_pos - self.max_pos / 25.);
+								self.cur_pos =
+									utils::max(self.min_pos, self.cur_pos - self.range() / 25.);
 								return Some((self.action_fn)(self.cur_pos));
 							}
 						}
@@ -399,7 +430,7 @@ impl Slider
 							{
 								state.sfx.play_sound("data/ui2.ogg").unwrap();
 								self.cur_pos =
-									utils::min(self.max_pos, self.cur_pos + self.max_pos / 25.);
+									utils::min(self.max_pos, self.cur_pos + self.range() / 25.);
 								return Some((self.action_fn)(self.cur_pos));
 							}
 						}
@@ -667,6 +698,18 @@ impl WidgetList
 		}
 	}
 
+	pub fn shift(&mut self, offset: Vector2<f32>)
+	{
+		for row in self.widgets.iter_mut()
+		{
+			for w in row.iter_mut()
+			{
+				let loc = w.loc() + offset;
+				w.set_loc(loc);
+			}
+		}
+	}
+
 	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
 	{
 		let mut action = None;
@@ -778,16 +821,22 @@ impl WidgetList
 pub struct MainMenu
 {
 	widgets: WidgetList,
+	display_width: f32,
+	display_height: f32,
 }
 
 impl MainMenu
 {
-	pub fn new(_display_width: f32, display_height: f32) -> Self
+	pub fn new(state: &game_state::GameState, display_width: f32, display_height: f32) -> Self
 	{
 		let w = 192.;
 		let h = 16.;
 		let h_space = 8.;
-		let cx = 128.;
+		let bitmap_width = state
+			.get_bitmap("data/main_menu.png")
+			.map(|b| b.get_width() as f32)
+			.unwrap_or(display_width);
+		let cx = (display_width - bitmap_width) / 2. + 128.;
 		let cy = display_height / 2.;
 
 		Self {
@@ -827,6 +876,14 @@ impl MainMenu
 							SubScreen::OptionsMenu(OptionsMenu::new(s, dx, dy))
 						}),
 					))],
+					&[Widget::Button(Button::new(
+						0.,
+						0.,
+						w,
+						h,
+						"CREDITS",
+						Action::Credits,
+					))],
 					&[Widget::Button(Button::new(
 						0.,
 						0.,
@@ -837,15 +894,18 @@ impl MainMenu
 					))],
 				],
 			),
+			display_width: display_width,
+			display_height: display_height,
 		}
 	}
 
 	pub fn draw(&self, state: &game_state::GameState)
 	{
+		let bitmap = state.get_bitmap("data/main_menu.png").unwrap();
 		state.core.draw_bitmap(
-			state.get_bitmap("data/main_menu.png").unwrap(),
-			0.,
-			0.,
+			bitmap,
+			((self.display_width - bitmap.get_width() as f32) / 2.).floor(),
+			((self.display_height - bitmap.get_height() as f32) / 2.).floor(),
 			Flag::zero(),
 		);
 		self.widgets.draw(state);
@@ -873,7 +933,7 @@ impl LevelMenu
 		let cy = display_height / 2.;
 
 		let mut buttons = vec![];
-		for level in &state.levels.levels
+		for level in state.campaign.levels()
 		{
 			if state.options.unlocked.contains(&level.name)
 			{
@@ -945,7 +1005,7 @@ impl ControlsMenu
 				h,
 				state.controls.get_mouse_sensitivity(),
 				2.,
-				|i| Action::MouseSensitivity(i),
+				Action::MouseSensitivity,
 			)),
 		]);
 
@@ -1096,6 +1156,10 @@ impl ControlsMenu
 	}
 }
 
+// Field of view range, in degrees.
+const MIN_FOV: f32 = 45.;
+const MAX_FOV: f32 = 150.;
+
 pub struct OptionsMenu
 {
 	widgets: WidgetList,
@@ -1112,40 +1176,157 @@ impl OptionsMenu
 		let cy = display_height / 2.;
 
 		let widgets = [
+			vec![Widget::Button(Button::new(
+				0.,
+				0.,
+				w,
+				h,
+				"VIDEO",
+				Action::Forward(|s, dx, dy| {
+					SubScreen::VideoMenu(Box::new(VideoMenu::new(s, dx, dy)))
+				}),
+			))],
 			vec![
-				Widget::Label(Label::new(0., 0., w, h, "FULLSCREEN")),
-				Widget::Toggle(Toggle::new(
+				Widget::Label(Label::new(0., 0., w, h, "MUSIC VOLUME")),
+				Widget::Slider(Slider::new(
 					0.,
 					0.,
 					w,
 					h,
-					state.options.fullscreen as usize,
-					vec!["NO".into(), "YES".into()],
-					|_| Action::ToggleFullscreen,
+					state.options.music_volume,
+					4.,
+					Action::MusicVolume,
 				)),
 			],
 			vec![
-				Widget::Label(Label::new(0., 0., w, h, "MUSIC VOLUME")),
+				Widget::Label(Label::new(0., 0., w, h, "SFX VOLUME")),
 				Widget::Slider(Slider::new(
 					0.,
 					0.,
 					w,
 					h,
-					state.options.music_volume,
+					state.options.sfx_volume,
 					4.,
-					|i| Action::MusicVolume(i),
+					Action::SfxVolume,
 				)),
 			],
 			vec![
-				Widget::Label(Label::new(0., 0., w, h, "SFX VOLUME")),
+				Widget::Label(Label::new(0., 0., w, h, "WEAPONS VOLUME")),
 				Widget::Slider(Slider::new(
 					0.,
 					0.,
 					w,
 					h,
-					state.options.sfx_volume,
-					4.,
-					|i| Action::SfxVolume(i),
+					state.options.category_volume(sfx::SoundCategory::Weapons),
+					2.,
+					|i| Action::CategoryVolume(sfx::SoundCategory::Weapons, i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "MONSTERS VOLUME")),
+				Widget::Slider(Slider::new(
+					0.,
+					0.,
+					w,
+					h,
+					state.options.category_volume(sfx::SoundCategory::Monsters),
+					2.,
+					|i| Action::CategoryVolume(sfx::SoundCategory::Monsters, i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "INTERFACE VOLUME")),
+				Widget::Slider(Slider::new(
+					0.,
+					0.,
+					w,
+					h,
+					state.options.category_volume(sfx::SoundCategory::Ui),
+					2.,
+					|i| Action::CategoryVolume(sfx::SoundCategory::Ui, i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "AMBIENCE VOLUME")),
+				Widget::Slider(Slider::new(
+					0.,
+					0.,
+					w,
+					h,
+					state.options.category_volume(sfx::SoundCategory::Ambience),
+					2.,
+					|i| Action::CategoryVolume(sfx::SoundCategory::Ambience, i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "VOICE VOLUME")),
+				Widget::Slider(Slider::new(
+					0.,
+					0.,
+					w,
+					h,
+					state.options.category_volume(sfx::SoundCategory::Voice),
+					2.,
+					|i| Action::CategoryVolume(sfx::SoundCategory::Voice, i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "CAPTIONS")),
+				Widget::Toggle(Toggle::new(
+					0.,
+					0.,
+					w,
+					h,
+					state.options.captions as usize,
+					vec!["NO".into(), "YES".into()],
+					|_| Action::ToggleCaptions,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "CAMERA")),
+				Widget::Toggle(Toggle::new(
+					0.,
+					0.,
+					w,
+					h,
+					match state.options.camera_mode
+					{
+						game_state::CameraMode::ThirdPerson => 0,
+						game_state::CameraMode::FirstPerson => 1,
+					},
+					vec!["THIRD PERSON".into(), "FIRST PERSON".into()],
+					|_| Action::ToggleCameraMode,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "FIELD OF VIEW")),
+				Widget::Slider(
+					Slider::new(0., 0., w, h, state.options.fov, MAX_FOV, Action::Fov)
+						.with_min(MIN_FOV),
+				),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "SCREEN SHAKE")),
+				Widget::Slider(Slider::new(
+					0.,
+					0.,
+					w,
+					h,
+					state.options.screen_shake,
+					2.,
+					Action::ScreenShake,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "VEHICLE CAMERA LAG")),
+				Widget::Toggle(Toggle::new(
+					0.,
+					0.,
+					w,
+					h,
+					state.options.camera_smoothing as usize,
+					vec!["NO".into(), "YES".into()],
+					|_| Action::ToggleCameraSmoothing,
 				)),
 			],
 			vec![Widget::Button(Button::new(
@@ -1182,11 +1363,6 @@ impl OptionsMenu
 		{
 			match action
 			{
-				Action::ToggleFullscreen =>
-				{
-					state.options.fullscreen = !state.options.fullscreen;
-					options_changed = true;
-				}
 				Action::MusicVolume(v) =>
 				{
 					state.options.music_volume = v;
@@ -1199,6 +1375,41 @@ impl OptionsMenu
 					state.sfx.set_sfx_volume(v);
 					options_changed = true;
 				}
+				Action::CategoryVolume(category, v) =>
+				{
+					state.options.set_category_volume(category, v);
+					state.sfx.set_category_volume(category, v);
+					options_changed = true;
+				}
+				Action::ToggleCaptions =>
+				{
+					state.options.captions = !state.options.captions;
+					options_changed = true;
+				}
+				Action::ToggleCameraMode =>
+				{
+					state.options.camera_mode = match state.options.camera_mode
+					{
+						game_state::CameraMode::ThirdPerson => game_state::CameraMode::FirstPerson,
+						game_state::CameraMode::FirstPerson => game_state::CameraMode::ThirdPerson,
+					};
+					options_changed = true;
+				}
+				Action::Fov(v) =>
+				{
+					state.options.fov = utils::clamp(v, MIN_FOV, MAX_FOV);
+					options_changed = true;
+				}
+				Action::ScreenShake(v) =>
+				{
+					state.options.screen_shake = v;
+					options_changed = true;
+				}
+				Action::ToggleCameraSmoothing =>
+				{
+					state.options.camera_smoothing = !state.options.camera_smoothing;
+					options_changed = true;
+				}
 				_ => return Some(action),
 			}
 		}
@@ -1210,16 +1421,366 @@ impl OptionsMenu
 	}
 }
 
+const RENDER_RESOLUTIONS: [(i32, i32); 5] =
+	[(640, 480), (800, 600), (1024, 768), (854, 480), (1280, 720)];
+
+// Seconds before unconfirmed video settings are reverted.
+const REVERT_DELAY: f64 = 10.;
+
+pub struct VideoMenu
+{
+	widgets: WidgetList,
+	confirm_widgets: WidgetList,
+	display_modes: Vec<(i32, i32)>,
+	render_resolutions: Vec<(i32, i32)>,
+	new_options: game_state::Options,
+	old_options: Option<game_state::Options>,
+	revert_time: f64,
+	error: String,
+	display_width: f32,
+	display_height: f32,
+}
+
+impl VideoMenu
+{
+	pub fn new(state: &game_state::GameState, display_width: f32, display_height: f32) -> Self
+	{
+		let w = 256.;
+		let h = 16.;
+		let h_space = 8.;
+		let cx = display_width / 2.;
+		let cy = display_height / 2.;
+
+		let options = &state.options;
+
+		let mut display_modes = video::get_display_modes();
+		if !display_modes.contains(&(options.width, options.height))
+		{
+			display_modes.push((options.width, options.height));
+			display_modes.sort();
+		}
+		let display_mode_idx = display_modes
+			.iter()
+			.position(|&m| m == (options.width, options.height))
+			.unwrap();
+
+		let mut render_resolutions = RENDER_RESOLUTIONS.to_vec();
+		if !render_resolutions.contains(&(options.render_width, options.render_height))
+		{
+			render_resolutions.push((options.render_width, options.render_height));
+		}
+		let render_resolution_idx = match options.render_mode
+		{
+			game_state::RenderMode::Fixed => render_resolutions
+				.iter()
+				.position(|&r| r == (options.render_width, options.render_height))
+				.unwrap(),
+			game_state::RenderMode::Native => render_resolutions.len(),
+		};
+		let mut render_resolution_texts: Vec<String> = render_resolutions
+			.iter()
+			.map(|(w, h)| format!("{}X{}", w, h))
+			.collect();
+		render_resolution_texts.push("NATIVE".into());
+
+		let widgets = [
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "WINDOW MODE")),
+				Widget::Toggle(Toggle::new(
+					0.,
+					0.,
+					w,
+					h,
+					match (options.fullscreen, options.exclusive_fullscreen)
+					{
+						(false, _) => 0,
+						(true, false) => 1,
+						(true, true) => 2,
+					},
+					vec![
+						"WINDOWED".into(),
+						"FULLSCREEN WINDOW".into(),
+						"FULLSCREEN".into(),
+					],
+					Action::WindowMode,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "RESOLUTION")),
+				Widget::Toggle(Toggle::new(
+					0.,
+					0.,
+					w,
+					h,
+					display_mode_idx,
+					display_modes
+						.iter()
+						.map(|(w, h)| format!("{}X{}", w, h))
+						.collect(),
+					Action::DisplayMode,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "VSYNC")),
+				Widget::Toggle(Toggle::new(
+					0.,
+					0.,
+					w,
+					h,
+					utils::clamp(options.vsync_method, 0, 2) as usize,
+					vec!["OFF".into(), "DRIVER".into(), "WAIT".into()],
+					Action::VsyncMethod,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "RENDER RESOLUTION")),
+				Widget::Toggle(Toggle::new(
+					0.,
+					0.,
+					w,
+					h,
+					render_resolution_idx,
+					render_resolution_texts,
+					Action::RenderResolution,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "SCALING")),
+				Widget::Toggle(Toggle::new(
+					0.,
+					0.,
+					w,
+					h,
+					match options.scaling_mode
+					{
+						game_state::ScalingMode::Nearest => 0,
+						game_state::ScalingMode::Integer => 1,
+						game_state::ScalingMode::Smooth => 2,
+					},
+					vec!["NEAREST".into(), "INTEGER".into(), "SMOOTH".into()],
+					Action::ScalingMode,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(0., 0., w, h, "FILTER")),
+				Widget::Toggle(Toggle::new(
+					0.,
+					0.,
+					w,
+					h,
+					match options.post_process
+					{
+						game_state::PostProcess::Off => 0,
+						game_state::PostProcess::Crt => 1,
+						game_state::PostProcess::RetroPalette => 2,
+						game_state::PostProcess::Colorblind => 3,
+						game_state::PostProcess::Custom => 4,
+					},
+					vec![
+						"OFF".into(),
+						"CRT".into(),
+						"RETRO PALETTE".into(),
+						"COLORBLIND".into(),
+						"CUSTOM".into(),
+					],
+					Action::PostProcess,
+				)),
+			],
+			vec![
+				Widget::Button(Button::new(0., 0., w, h, "APPLY", Action::ApplyVideo)),
+				Widget::Button(Button::new(0., 0., w, h, "BACK", Action::Back)),
+			],
+		];
+
+		let confirm_widgets = [
+			vec![Widget::Label(Label::new(
+				0.,
+				0.,
+				w,
+				h,
+				"KEEP THESE SETTINGS?",
+			))],
+			vec![
+				Widget::Button(Button::new(0., 0., w, h, "KEEP", Action::KeepVideo)),
+				Widget::Button(Button::new(0., 0., w, h, "REVERT", Action::RevertVideo)),
+			],
+		];
+
+		Self {
+			widgets: WidgetList::new(
+				cx,
+				cy,
+				h,
+				h_space,
+				&widgets.iter().map(|r| &r[..]).collect::<Vec<_>>(),
+			),
+			confirm_widgets: WidgetList::new(
+				cx,
+				cy,
+				h,
+				h_space,
+				&confirm_widgets.iter().map(|r| &r[..]).collect::<Vec<_>>(),
+			),
+			display_modes: display_modes,
+			render_resolutions: render_resolutions,
+			new_options: options.clone(),
+			old_options: None,
+			revert_time: 0.,
+			error: "".into(),
+			display_width: display_width,
+			display_height: display_height,
+		}
+	}
+
+	pub fn draw(&self, state: &game_state::GameState)
+	{
+		if self.old_options.is_some()
+		{
+			self.confirm_widgets.draw(state);
+			let time_left = utils::max(0., self.revert_time - state.core.get_time());
+			state.core.draw_text(
+				&state.ui_font,
+				Color::from_rgb_f(0.6, 0.6, 0.4),
+				self.display_width / 2.,
+				self.display_height / 2. + 32.,
+				FontAlign::Centre,
+				&format!("REVERTING IN {}", time_left.ceil() as i32),
+			);
+		}
+		else
+		{
+			self.widgets.draw(state);
+			if !self.error.is_empty()
+			{
+				state.core.draw_text(
+					&state.ui_font,
+					Color::from_rgb_f(0.9, 0.4, 0.4),
+					self.display_width / 2.,
+					self.display_height - 48.,
+					FontAlign::Centre,
+					&self.error,
+				);
+			}
+		}
+	}
+
+	// The new settings never took effect, so there's nothing to confirm.
+	pub fn display_failed(&mut self, state: &mut game_state::GameState, error: &str)
+	{
+		if let Some(old_options) = self.old_options.take()
+		{
+			state.options = old_options;
+		}
+		*self = VideoMenu::new(state, self.display_width, self.display_height);
+		self.error = error.to_uppercase();
+	}
+
+	pub fn input(
+		&mut self, state: &mut game_state::GameState, event: &Event,
+	) -> Result<Option<Action>>
+	{
+		if let Some(old_options) = &self.old_options
+		{
+			let mut action = self.confirm_widgets.input(state, event);
+			if action.is_none() && state.core.get_time() > self.revert_time
+			{
+				action = Some(Action::RevertVideo);
+			}
+			match action
+			{
+				Some(Action::KeepVideo) =>
+				{
+					self.old_options = None;
+					game_state::save_options(&state.core, &state.options)?;
+				}
+				Some(Action::RevertVideo) | Some(Action::Back) =>
+				{
+					state.options = old_options.clone();
+					*self = VideoMenu::new(state, self.display_width, self.display_height);
+				}
+				_ => (),
+			}
+			return Ok(None);
+		}
+
+		let action = self.widgets.input(state, event);
+		if let Some(action) = action
+		{
+			match action
+			{
+				Action::WindowMode(i) =>
+				{
+					self.new_options.fullscreen = i > 0;
+					self.new_options.exclusive_fullscreen = i == 2;
+				}
+				Action::DisplayMode(i) =>
+				{
+					let (width, height) = self.display_modes[i];
+					self.new_options.width = width;
+					self.new_options.height = height;
+				}
+				Action::VsyncMethod(i) =>
+				{
+					self.new_options.vsync_method = i as i32;
+				}
+				Action::RenderResolution(i) =>
+				{
+					if i < self.render_resolutions.len()
+					{
+						let (width, height) = self.render_resolutions[i];
+						self.new_options.render_mode = game_state::RenderMode::Fixed;
+						self.new_options.render_width = width;
+						self.new_options.render_height = height;
+					}
+					else
+					{
+						self.new_options.render_mode = game_state::RenderMode::Native;
+					}
+				}
+				Action::ScalingMode(i) =>
+				{
+					self.new_options.scaling_mode = match i
+					{
+						0 => game_state::ScalingMode::Nearest,
+						1 => game_state::ScalingMode::Integer,
+						_ => game_state::ScalingMode::Smooth,
+					};
+				}
+				Action::PostProcess(i) =>
+				{
+					self.new_options.post_process = match i
+					{
+						0 => game_state::PostProcess::Off,
+						1 => game_state::PostProcess::Crt,
+						2 => game_state::PostProcess::RetroPalette,
+						3 => game_state::PostProcess::Colorblind,
+						_ => game_state::PostProcess::Custom,
+					};
+				}
+				Action::ApplyVideo =>
+				{
+					self.old_options = Some(state.options.clone());
+					state.options = self.new_options.clone();
+					self.revert_time = state.core.get_time() + REVERT_DELAY;
+				}
+				_ => return Ok(Some(action)),
+			}
+		}
+		Ok(None)
+	}
+}
+
 pub struct CharacterMenu
 {
 	widgets: WidgetList,
+	sprite_sheets: Vec<String>,
 	display_width: f32,
 	display_height: f32,
 }
 
 impl CharacterMenu
 {
-	pub fn new(_state: &game_state::GameState, display_width: f32, display_height: f32) -> Self
+	pub fn new(state: &game_state::GameState, display_width: f32, display_height: f32) -> Self
 	{
 		let w = 192.;
 		let h = 16.;
@@ -1228,30 +1789,25 @@ impl CharacterMenu
 		let cy = display_height / 2.;
 
 		let mut buttons = vec![];
-
-		buttons.push(Widget::Button(Button::new(
-			0.,
-			0.,
-			w,
-			h,
-			"SANTA",
-			Action::SelectCharacter(game_state::PlayerClass::Santa),
-		)));
-
-		buttons.push(Widget::Button(Button::new(
-			0.,
-			0.,
-			w,
-			h,
-			"CYBER RUDE-OLF",
-			Action::SelectCharacter(game_state::PlayerClass::Reindeer),
-		)));
+		let mut sprite_sheets = vec![];
+		for class in &state.player_classes.classes
+		{
+			buttons.push(Widget::Button(Button::new(
+				0.,
+				0.,
+				w,
+				h,
+				&class.name,
+				Action::SelectCharacter(class.name.clone()),
+			)));
+			sprite_sheets.push(class.sprite_sheet.clone());
+		}
 
 		Self {
 			widgets: WidgetList::new(
 				cx,
 				cy + 128.,
-				512. - w,
+				Self::spacing(sprite_sheets.len()) - w,
 				h_space,
 				&[
 					&buttons,
@@ -1265,68 +1821,58 @@ impl CharacterMenu
 					))],
 				],
 			),
+			sprite_sheets: sprite_sheets,
 			display_width: display_width,
 			display_height: display_height,
 		}
 	}
 
+	// Distance between the centers of adjacent characters.
+	fn spacing(num_classes: usize) -> f32
+	{
+		512. / utils::max(num_classes as f32 - 1., 1.)
+	}
+
 	pub fn draw(&self, state: &game_state::GameState)
 	{
 		self.widgets.draw(state);
 
 		let cx = self.display_width / 2.;
 		let cy = self.display_height / 2.;
+		let spacing = Self::spacing(self.sprite_sheets.len());
+		let start_x = cx - spacing * (self.sprite_sheets.len() as f32 - 1.) / 2.;
 
-		let sheet = state.get_sprite_sheet("data/santa.cfg").unwrap();
-		if let Some(atlas_bmp) = sheet.get_bitmap(
-			state.time(),
-			2. * state.time() as f32,
-			0.,
-			None,
-			Some(components::Velocity {
-				vel: Vector3::new(64., 0., 0.),
-				dir_vel: 0.,
-			}),
-		)
-		{
-			let w = atlas_bmp.width();
-			let h = atlas_bmp.height();
-			state.core.draw_bitmap_region(
-				&state.atlas.pages[atlas_bmp.page].bitmap,
-				atlas_bmp.start.x,
-				atlas_bmp.start.y,
-				w,
-				h,
-				cx - 256. - w / 2.,
-				cy - 64.,
-				Flag::zero(),
-			);
-		}
-
-		let sheet = state.get_sprite_sheet("data/reindeer.cfg").unwrap();
-		if let Some(atlas_bmp) = sheet.get_bitmap(
-			state.time(),
-			2. * state.time() as f32,
-			0.,
-			None,
-			Some(components::Velocity {
-				vel: Vector3::new(64., 0., 0.),
-				dir_vel: 0.,
-			}),
-		)
+		for (i, sprite_sheet) in self.sprite_sheets.iter().enumerate()
 		{
-			let w = atlas_bmp.width();
-			let h = atlas_bmp.height();
-			state.core.draw_bitmap_region(
-				&state.atlas.pages[atlas_bmp.page].bitmap,
-				atlas_bmp.start.x,
-				atlas_bmp.start.y,
-				w,
-				h,
-				cx + 256. - w / 2.,
-				cy - 64.,
-				Flag::zero(),
-			);
+			let sheet = match state.get_sprite_sheet(sprite_sheet)
+			{
+				Some(sheet) => sheet,
+				None => continue,
+			};
+			if let Some(atlas_bmp) = sheet.get_bitmap(
+				state.time(),
+				2. * state.time() as f32,
+				0.,
+				None,
+				Some(components::Velocity {
+					vel: Vector3::new(64., 0., 0.),
+					dir_vel: 0.,
+				}),
+			)
+			{
+				let w = atlas_bmp.width();
+				let h = atlas_bmp.height();
+				state.core.draw_bitmap_region(
+					&state.atlas.pages[atlas_bmp.page].bitmap,
+					atlas_bmp.start.x,
+					atlas_bmp.start.y,
+					w,
+					h,
+					start_x + spacing * i as f32 - w / 2.,
+					cy - 64.,
+					Flag::zero(),
+				);
+			}
 		}
 	}
 
@@ -1339,11 +1885,14 @@ impl CharacterMenu
 pub struct InGameMenu
 {
 	widgets: WidgetList,
+	objectives: Vec<(String, Color)>,
+	display_width: f32,
+	display_height: f32,
 }
 
 impl InGameMenu
 {
-	pub fn new(display_width: f32, display_height: f32) -> Self
+	pub fn new(objectives: Vec<(String, Color)>, display_width: f32, display_height: f32) -> Self
 	{
 		let w = 192.;
 		let h = 16.;
@@ -1386,6 +1935,14 @@ impl InGameMenu
 							SubScreen::OptionsMenu(OptionsMenu::new(s, dx, dy))
 						}),
 					))],
+					&[Widget::Button(Button::new(
+						0.,
+						0.,
+						w,
+						h,
+						"MESSAGES",
+						Action::MessageLog,
+					))],
 					&[Widget::Button(Button::new(
 						0.,
 						0.,
@@ -1396,11 +1953,123 @@ impl InGameMenu
 					))],
 				],
 			),
+			objectives: objectives,
+			display_width: display_width,
+			display_height: display_height,
 		}
 	}
 
 	pub fn draw(&self, state: &game_state::GameState)
 	{
+		// Above the buttons.
+		let y = self.display_height / 2. - 96. - 24. * self.objectives.len() as f32;
+		for (i, (line, color)) in self.objectives.iter().enumerate()
+		{
+			state.core.draw_text(
+				&state.ui_font,
+				*color,
+				self.display_width / 2.,
+				y + 24. * i as f32,
+				FontAlign::Centre,
+				line,
+			);
+		}
+		self.widgets.draw(state);
+	}
+
+	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
+	{
+		self.widgets.input(state, event)
+	}
+}
+
+pub struct MessageLog
+{
+	widgets: WidgetList,
+	// Wrapped lines, oldest first.
+	lines: Vec<(String, Color)>,
+	display_width: f32,
+	display_height: f32,
+}
+
+impl MessageLog
+{
+	pub fn new(
+		state: &game_state::GameState, messages: &messages::MessageQueue, display_width: f32,
+		display_height: f32,
+	) -> Self
+	{
+		let w = 192.;
+		let h = 16.;
+		let h_space = 8.;
+
+		let width = utils::min(display_width - 32., messages::MAX_WIDTH);
+		let mut lines = vec![];
+		for message in messages.history()
+		{
+			let text = if message.speaker.is_empty()
+			{
+				message.text.clone()
+			}
+			else
+			{
+				format!("{}: {}", message.speaker, message.text)
+			};
+			for line in messages::wrap_text(&state.ui_font, &text, width)
+			{
+				lines.push((line, message.color));
+			}
+		}
+
+		Self {
+			widgets: WidgetList::new(
+				display_width / 2.,
+				display_height - 32.,
+				h,
+				h_space,
+				&[&[Widget::Button(Button::new(
+					0.,
+					0.,
+					w,
+					h,
+					"BACK",
+					Action::Back,
+				))]],
+			),
+			lines: lines,
+			display_width: display_width,
+			display_height: display_height,
+		}
+	}
+
+	pub fn draw(&self, state: &game_state::GameState)
+	{
+		// Show the most recent lines that fit above the button.
+		let line_height = 24.;
+		let max_lines = utils::max(0., (self.display_height - 80.) / line_height) as usize;
+		let start = self.lines.len().saturating_sub(max_lines);
+		for (i, (line, color)) in self.lines[start..].iter().enumerate()
+		{
+			state.core.draw_text(
+				&state.ui_font,
+				*color,
+				self.display_width / 2.,
+				16. + line_height * i as f32,
+				FontAlign::Centre,
+				line,
+			);
+		}
+		if self.lines.is_empty()
+		{
+			state.core.draw_text(
+				&state.ui_font,
+				Color::from_rgb_f(0.8, 0.8, 0.5),
+				self.display_width / 2.,
+				16.,
+				FontAlign::Centre,
+				"NO MESSAGES",
+			);
+		}
 		self.widgets.draw(state);
 	}
 
@@ -1417,11 +2086,62 @@ pub enum SubScreen
 	ControlsMenu(ControlsMenu),
 	CharacterMenu(CharacterMenu),
 	OptionsMenu(OptionsMenu),
+	VideoMenu(Box<VideoMenu>),
 	InGameMenu(InGameMenu),
+	MessageLog(MessageLog),
 }
 
 impl SubScreen
 {
+	pub fn resize(&mut self, display_width: f32, display_height: f32, offset: Vector2<f32>)
+	{
+		match self
+		{
+			SubScreen::MainMenu(s) =>
+			{
+				s.display_width = display_width;
+				s.display_height = display_height;
+				s.widgets.shift(offset);
+			}
+			SubScreen::CharacterMenu(s) =>
+			{
+				s.display_width = display_width;
+				s.display_height = display_height;
+				s.widgets.shift(offset);
+			}
+			SubScreen::LevelMenu(s) => s.widgets.shift(offset),
+			SubScreen::ControlsMenu(s) => s.widgets.shift(offset),
+			SubScreen::OptionsMenu(s) => s.widgets.shift(offset),
+			SubScreen::VideoMenu(s) =>
+			{
+				s.display_width = display_width;
+				s.display_height = display_height;
+				s.widgets.shift(offset);
+				s.confirm_widgets.shift(offset);
+			}
+			SubScreen::InGameMenu(s) =>
+			{
+				s.display_width = display_width;
+				s.display_height = display_height;
+				s.widgets.shift(offset);
+			}
+			SubScreen::MessageLog(s) =>
+			{
+				s.display_width = display_width;
+				s.display_height = display_height;
+				s.widgets.shift(offset);
+			}
+		}
+	}
+
+	pub fn display_failed(&mut self, state: &mut game_state::GameState, error: &str)
+	{
+		if let SubScreen::VideoMenu(s) = self
+		{
+			s.display_failed(state, error);
+		}
+	}
+
 	pub fn draw(&self, state: &game_state::GameState)
 	{
 		match self
@@ -1431,20 +2151,26 @@ impl SubScreen
 			SubScreen::ControlsMenu(s) => s.draw(state),
 			SubScreen::CharacterMenu(s) => s.draw(state),
 			SubScreen::OptionsMenu(s) => s.draw(state),
+			SubScreen::VideoMenu(s) => s.draw(state),
 			SubScreen::InGameMenu(s) => s.draw(state),
+			SubScreen::MessageLog(s) => s.draw(state),
 		}
 	}
 
-	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
+	pub fn input(
+		&mut self, state: &mut game_state::GameState, event: &Event,
+	) -> Result<Option<Action>>
 	{
-		match self
+		Ok(match self
 		{
 			SubScreen::MainMenu(s) => s.input(state, event),
 			SubScreen::LevelMenu(s) => s.input(state, event),
 			SubScreen::ControlsMenu(s) => s.input(state, event),
 			SubScreen::CharacterMenu(s) => s.input(state, event),
 			SubScreen::OptionsMenu(s) => s.input(state, event),
+			SubScreen::VideoMenu(s) => s.input(state, event)?,
 			SubScreen::InGameMenu(s) => s.input(state, event),
-		}
+			SubScreen::MessageLog(s) => s.input(state, event),
+		})
 	}
 }
@@ -20,9 +20,9 @@ use na::{
 };
 use nalgebra as na;
 
-pub fn projection_transform(dw: f32, dh: f32) -> Perspective3<f32>
+pub fn projection_transform(dw: f32, dh: f32, fov: f32) -> Perspective3<f32>
 {
-	Perspective3::new(dw / dh, f32::pi() / 2., 1., 2000.)
+	Perspective3::new(dw / dh, fov * f32::pi() / 180., 1., 2000.)
 }
 
 pub fn mat4_to_transform(mat: Matrix4<f32>) -> Transform
@@ -38,14 +38,33 @@ pub fn mat4_to_transform(mat: Matrix4<f32>) -> Transform
 	trans
 }
 
-pub fn camera_project(x: f32, y: f32, z: f32, player_x: f32, player_z: f32) -> Isometry3<f32>
+// Sets up the projection and render state for drawing 2D screens and overlays.
+pub fn use_2d_projection(core: &Core, width: f32, height: f32)
+{
+	let ortho_mat = Matrix4::new_orthographic(0., width, height, 0., -1., 1.);
+	unsafe {
+		gl::Disable(gl::CULL_FACE);
+	}
+	core.use_projection_transform(&mat4_to_transform(ortho_mat));
+	core.use_transform(&Transform::identity());
+	core.set_depth_test(None);
+	unsafe {
+		allegro_sys::al_set_render_state(allegro_sys::ALLEGRO_ALPHA_TEST_RS, 0);
+	}
+}
+
+pub fn camera_project(eye: Point3<f32>, target: Point3<f32>) -> Isometry3<f32>
 {
-	let eye = Point3::new(x, y, z);
-	let target = Point3::new(player_x, y, player_z);
 	let view = Isometry3::look_at_rh(&eye, &target, &Vector3::y());
 	view
 }
 
+// Wrapped to [-pi, pi).
+pub fn angle_diff(from: f32, to: f32) -> f32
+{
+	(to - from + PI).rem_euclid(2. * PI) - PI
+}
+
 pub fn random_color(seed: u64, saturation: f32, value: f32) -> Color
 {
 	let mut rng = StdRng::seed_from_u64(seed);
@@ -155,6 +174,48 @@ pub fn load_bitmap(core: &Core, file: &str) -> Result<Bitmap>
 	Ok(Bitmap::load(&core, file).map_err(|_| format!("Couldn't load {}", file))?)
 }
 
+pub fn save_bitmap(bitmap: &Bitmap, file: &str) -> Result<()>
+{
+	use std::ffi::CString;
+	let c_file = CString::new(file.as_bytes()).unwrap();
+	if unsafe { allegro_sys::al_save_bitmap(c_file.as_ptr(), bitmap.get_allegro_bitmap()) != 0 }
+	{
+		Ok(())
+	}
+	else
+	{
+		Err(format!("Couldn't save '{}'", file).into())
+	}
+}
+
+// UTC, as YYYY-MM-DD_HH-MM-SS.
+pub fn format_timestamp(secs: u64) -> String
+{
+	let days = (secs / 86400) as i64;
+	let rem = secs % 86400;
+
+	// Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+	format!(
+		"{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+		year,
+		month,
+		day,
+		rem / 3600,
+		rem / 60 % 60,
+		rem % 60
+	)
+}
+
 pub fn load_sample(audio: &AudioAddon, path: &str) -> Result<Sample>
 {
 	Ok(Sample::load(audio, path).map_err(|_| format!("Couldn't load '{}'", path))?)
@@ -206,6 +267,24 @@ pub fn nearest_poly_point(vs: &[Point2<f32>], test_point: Point2<f32>) -> Point2
 	best_point
 }
 
+// Returns the point `f` (0 to 1) of the way along a path of line segments.
+pub fn point_along_path(path: &[Point3<f32>], f: f32) -> Point3<f32>
+{
+	assert!(!path.is_empty());
+	let total: f32 = path.windows(2).map(|w| (w[1] - w[0]).norm()).sum();
+	let mut dist = clamp(f, 0., 1.) * total;
+	for w in path.windows(2)
+	{
+		let len = (w[1] - w[0]).norm();
+		if dist <= len && len > 0.
+		{
+			return w[0] + (w[1] - w[0]) * dist / len;
+		}
+		dist -= len;
+	}
+	path[path.len() - 1]
+}
+
 pub fn is_inside_poly(vs: &[Point2<f32>], test_point: Point2<f32>) -> bool
 {
 	// Clockwise.
@@ -229,6 +308,26 @@ pub fn is_inside_poly(vs: &[Point2<f32>], test_point: Point2<f32>) -> bool
 	true
 }
 
+// Crossing number test, works for any simple polygon regardless of winding.
+pub fn is_inside_simple_poly(vs: &[Point2<f32>], test_point: Point2<f32>) -> bool
+{
+	let mut inside = false;
+	for idx in 0..vs.len()
+	{
+		let v1 = vs[idx];
+		let v2 = vs[(idx + 1) % vs.len()];
+		if (v1.y > test_point.y) != (v2.y > test_point.y)
+		{
+			let cross_x = v1.x + (test_point.y - v1.y) / (v2.y - v1.y) * (v2.x - v1.x);
+			if test_point.x < cross_x
+			{
+				inside = !inside;
+			}
+		}
+	}
+	inside
+}
+
 // Stolen from ncollide.
 pub fn intersect_segment_segment(
 	start1: Point2<f32>, end1: Point2<f32>, start2: Point2<f32>, end2: Point2<f32>,
@@ -308,6 +407,23 @@ pub fn intersect_segment_segment(
 	(v1 - v2).norm_squared() < eps
 }
 
+#[test]
+fn test_angle_diff()
+{
+	assert!((angle_diff(0., PI / 2.) - PI / 2.).abs() < 1e-3);
+	assert!((angle_diff(PI / 2., 0.) + PI / 2.).abs() < 1e-3);
+	assert!((angle_diff(0.1, 2. * PI - 0.1) + 0.2).abs() < 1e-3);
+	assert!((angle_diff(8. * PI, 8. * PI + 0.5) - 0.5).abs() < 1e-3);
+}
+
+#[test]
+fn test_format_timestamp()
+{
+	assert_eq!(format_timestamp(0), "1970-01-01_00-00-00");
+	assert_eq!(format_timestamp(951782400), "2000-02-29_00-00-00");
+	assert_eq!(format_timestamp(1700000000), "2023-11-14_22-13-20");
+}
+
 #[test]
 fn test_nearest_line_point()
 {
@@ -354,6 +470,27 @@ fn test_is_inside_poly()
 	assert!(is_inside_poly(&vs, Point2::new(0., 0.)));
 }
 
+#[test]
+fn test_is_inside_simple_poly()
+{
+	// L shape.
+	let vs = [
+		Point2::new(0., 0.),
+		Point2::new(3., 0.),
+		Point2::new(3., 1.),
+		Point2::new(1., 1.),
+		Point2::new(1., 3.),
+		Point2::new(0., 3.),
+	];
+	assert!(is_inside_simple_poly(&vs, Point2::new(0.5, 2.)));
+	assert!(is_inside_simple_poly(&vs, Point2::new(2., 0.5)));
+	assert!(!is_inside_simple_poly(&vs, Point2::new(2., 2.)));
+
+	let reversed: Vec<_> = vs.iter().rev().cloned().collect();
+	assert!(is_inside_simple_poly(&reversed, Point2::new(0.5, 2.)));
+	assert!(!is_inside_simple_poly(&reversed, Point2::new(2., 2.)));
+}
+
 #[test]
 fn test_segment_segment()
 {
@@ -377,3 +514,18 @@ fn test_segment_segment2()
 
 	assert!(intersect_segment_segment(start1, end1, start2, end2));
 }
+
+#[test]
+fn test_point_along_path()
+{
+	let path = [
+		Point3::new(0., 0., 0.),
+		Point3::new(2., 0., 0.),
+		Point3::new(2., 0., 2.),
+	];
+	assert_eq!(point_along_path(&path, 0.), path[0]);
+	assert_eq!(point_along_path(&path, 0.25), Point3::new(1., 0., 0.));
+	assert_eq!(point_along_path(&path, 0.75), Point3::new(2., 0., 1.));
+	assert_eq!(point_along_path(&path, 2.), path[2]);
+	assert_eq!(point_along_path(&path[..1], 0.5), path[0]);
+}
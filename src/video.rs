@@ -0,0 +1,54 @@
+use std::os::raw::c_int;
+
+// allegro-sys 0.0.44 doesn't bind these yet.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct ALLEGRO_DISPLAY_MODE
+{
+	width: c_int,
+	height: c_int,
+	format: c_int,
+	refresh_rate: c_int,
+}
+
+extern "C" {
+	fn al_get_num_display_modes() -> c_int;
+	fn al_get_display_mode(
+		index: c_int, mode: *mut ALLEGRO_DISPLAY_MODE,
+	) -> *mut ALLEGRO_DISPLAY_MODE;
+	fn al_convert_memory_bitmaps();
+}
+
+pub fn get_display_modes() -> Vec<(i32, i32)>
+{
+	let mut modes = vec![];
+	unsafe {
+		for i in 0..al_get_num_display_modes()
+		{
+			let mut mode = ALLEGRO_DISPLAY_MODE {
+				width: 0,
+				height: 0,
+				format: 0,
+				refresh_rate: 0,
+			};
+			if !al_get_display_mode(i, &mut mode).is_null()
+			{
+				modes.push((mode.width, mode.height));
+			}
+		}
+	}
+	if modes.is_empty()
+	{
+		modes = vec![(800, 600), (1024, 768), (1280, 720), (1920, 1080)];
+	}
+	modes.sort();
+	modes.dedup();
+	modes
+}
+
+pub fn convert_memory_bitmaps()
+{
+	unsafe {
+		al_convert_memory_bitmaps();
+	}
+}